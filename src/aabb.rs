@@ -0,0 +1,118 @@
+use crate::point::Point;
+use crate::rendering::Ray;
+
+// axis-aligned bounding box
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub fn new(min: Point, max: Point) -> Aabb {
+        Aabb { min, max }
+    }
+
+    // returns box containing nothing, union with it gives the other box
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    // returns bounds of unbounded objects like planes
+    pub fn infinite() -> Aabb {
+        Aabb {
+            min: Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            max: Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        }
+    }
+
+    // returns smallest box containing all the points
+    pub fn from_points<'a, I: IntoIterator<Item = &'a Point>>(points: I) -> Aabb {
+        points
+            .into_iter()
+            .fold(Aabb::empty(), |bounds, p| bounds.include(p))
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.min.x.is_finite()
+            && self.min.y.is_finite()
+            && self.min.z.is_finite()
+            && self.max.x.is_finite()
+            && self.max.y.is_finite()
+            && self.max.z.is_finite()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Point::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn include(&self, point: &Point) -> Aabb {
+        self.union(&Aabb::new(*point, *point))
+    }
+
+    pub fn centroid(&self) -> Point {
+        Point::new(
+            (self.min.x + self.max.x) * 0.5,
+            (self.min.y + self.max.y) * 0.5,
+            (self.min.z + self.max.z) * 0.5,
+        )
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    // returns (enter, exit) distances of the ray inside the box
+    // clipped to 0.0…max_distance or None if the ray misses it
+    pub fn intersect(&self, ray: &Ray, max_distance: f64) -> Option<(f64, f64)> {
+        // slab method, division by zero gives infinities which compare correctly
+        let mut enter = 0.0_f64;
+        let mut exit = max_distance;
+        for &(origin, direction, min, max) in &[
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ] {
+            let inv_direction = direction.recip();
+            let mut t0 = (min - origin) * inv_direction;
+            let mut t1 = (max - origin) * inv_direction;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN appears when the origin is on the slab plane and the ray is parallel to it
+            if !t0.is_nan() {
+                enter = enter.max(t0);
+            }
+            if !t1.is_nan() {
+                exit = exit.min(t1);
+            }
+            if enter > exit {
+                return None;
+            }
+        }
+
+        Some((enter, exit))
+    }
+}
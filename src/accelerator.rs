@@ -0,0 +1,33 @@
+use crate::packet::{RayPacket, PACKET_SIZE};
+use crate::rendering::Ray;
+
+// Accelerator finds the closest of many primitives given by their bounds
+// without testing each of them, implemented by Bvh and KdTree
+pub trait Accelerator {
+    // returns index of the closest primitive and distance to it,
+    // intersect returns distance to the primitive with the given index
+    fn closest(
+        &self,
+        ray: &Ray,
+        intersect: &mut dyn FnMut(usize) -> Option<f64>,
+    ) -> Option<(usize, f64)>;
+    // returns true if occludes is true for any primitive whose bounds
+    // the ray meets closer than max_distance, stops at the first one
+    fn any_hit(
+        &self,
+        ray: &Ray,
+        max_distance: f64,
+        occludes: &mut dyn FnMut(usize) -> bool,
+    ) -> bool;
+    // returns the closest primitive for every ray of the packet, intersect
+    // returns distances to the primitive for the active lanes of the given
+    // packet, a part of the traced one; None if the structure has no packet
+    // traversal and rays have to be traced one by one
+    fn closest_packet(
+        &self,
+        _packet: &RayPacket,
+        _intersect: &mut dyn FnMut(usize, &RayPacket) -> [Option<f64>; PACKET_SIZE],
+    ) -> Option<[Option<(usize, f64)>; PACKET_SIZE]> {
+        None
+    }
+}
@@ -0,0 +1,378 @@
+use crate::aabb::Aabb;
+use crate::accelerator::Accelerator;
+use crate::packet::{RayPacket, PACKET_SIZE};
+use crate::point::Point;
+use crate::rendering::{update_closest, Ray};
+
+// number of buckets to evaluate split candidates per axis
+const BINS: usize = 12;
+// nodes with more primitives are split even if the heuristic prefers a leaf
+const MAX_LEAF_SIZE: usize = 4;
+// cost of visiting a node relative to intersecting one primitive
+const TRAVERSAL_COST: f64 = 1.0;
+// nodes this deep are leaves, it bounds the traversal stacks
+const MAX_DEPTH: usize = 64;
+// traversal visits one child and keeps the other for every level above
+const STACK_SIZE: usize = MAX_DEPTH + 1;
+
+struct Node {
+    bounds: Aabb,
+    // leaf: range of indices, interior: count is 0, the left child
+    // follows the node and first is the index of the right child
+    first: usize,
+    count: usize,
+    axis: usize,
+}
+
+// Bvh is a bounding volume hierarchy over primitives given by their bounds,
+// built with the surface area heuristic;
+// primitives with infinite bounds are kept aside and tested for every ray
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: vec![],
+            // primitives with empty bounds can not be hit
+            indices: (0..bounds.len())
+                .filter(|&i| bounds[i].is_finite() && !bounds[i].is_empty())
+                .collect(),
+            unbounded: (0..bounds.len())
+                .filter(|&i| !bounds[i].is_finite())
+                .collect(),
+        };
+        if !bvh.indices.is_empty() {
+            let centroids: Vec<Point> = bounds.iter().map(|b| b.centroid()).collect();
+            bvh.build(bounds, &centroids, 0, bvh.indices.len(), 0);
+        }
+
+        bvh
+    }
+
+    // builds node at the depth for indices[start..end] and returns its index
+    fn build(
+        &mut self,
+        bounds: &[Aabb],
+        centroids: &[Point],
+        start: usize,
+        end: usize,
+        depth: usize,
+    ) -> usize {
+        let node_index = self.nodes.len();
+        let items = &self.indices[start..end];
+        let node_bounds = items
+            .iter()
+            .fold(Aabb::empty(), |b, &i| b.union(&bounds[i]));
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            first: start,
+            count: end - start,
+            axis: 0,
+        });
+
+        let count = end - start;
+        if count <= 1 || depth == MAX_DEPTH {
+            return node_index;
+        }
+
+        let centroid_bounds = Aabb::from_points(items.iter().map(|&i| &centroids[i]));
+        let split = find_split(bounds, centroids, items, &node_bounds, &centroid_bounds);
+        let (axis, bin) = match split {
+            Some((axis, bin, cost)) if cost < count as f64 || count > MAX_LEAF_SIZE => (axis, bin),
+            // splitting does not pay off or all centroids are in one point
+            _ => return node_index,
+        };
+
+        let min = axis_value(&centroid_bounds.min, axis);
+        let extent = axis_value(&centroid_bounds.max, axis) - min;
+        let mut mid = start;
+        for j in start..end {
+            let centroid = axis_value(&centroids[self.indices[j]], axis);
+            if bin_index(centroid, min, extent) < bin {
+                self.indices.swap(mid, j);
+                mid += 1;
+            }
+        }
+        if mid == start || mid == end {
+            return node_index;
+        }
+
+        self.build(bounds, centroids, start, mid, depth + 1);
+        let right = self.build(bounds, centroids, mid, end, depth + 1);
+        let node = &mut self.nodes[node_index];
+        node.first = right;
+        node.count = 0;
+        node.axis = axis;
+
+        node_index
+    }
+
+    // returns index of the closest primitive and distance to it,
+    // intersect returns distance to the primitive with the given index
+    pub fn closest<F>(&self, ray: &Ray, mut intersect: F) -> Option<(usize, f64)>
+    where
+        F: FnMut(usize) -> Option<f64>,
+    {
+        let mut closest: Option<(usize, f64)> = None;
+        let mut test = |i: usize, closest: &mut Option<(usize, f64)>| {
+            if let Some(distance) = intersect(i) {
+                if closest.is_none_or(|(_, d)| distance < d) {
+                    *closest = Some((i, distance));
+                }
+            }
+        };
+
+        for &i in &self.unbounded {
+            test(i, &mut closest);
+        }
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let direction_negative = [
+            ray.direction.x < 0.0,
+            ray.direction.y < 0.0,
+            ray.direction.z < 0.0,
+        ];
+        let mut stack = [0; STACK_SIZE];
+        let mut stack_len = 1;
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node_index = stack[stack_len];
+            let node = &self.nodes[node_index];
+            let max_distance = closest.map_or(f64::INFINITY, |(_, d)| d);
+            if node.bounds.intersect(ray, max_distance).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                for &i in &self.indices[node.first..node.first + node.count] {
+                    test(i, &mut closest);
+                }
+            } else if direction_negative[node.axis] {
+                // visit the near child first so the far one is often culled
+                stack[stack_len] = node_index + 1;
+                stack[stack_len + 1] = node.first;
+                stack_len += 2;
+            } else {
+                stack[stack_len] = node.first;
+                stack[stack_len + 1] = node_index + 1;
+                stack_len += 2;
+            }
+        }
+
+        closest
+    }
+
+    // returns the closest primitive for every ray of the packet, nodes are
+    // visited while any lane meets their bounds before its current closest hit;
+    // intersect gets the packet with only the lanes meeting the leaf bounds active
+    pub fn closest_packet<F>(
+        &self,
+        packet: &RayPacket,
+        mut intersect: F,
+    ) -> [Option<(usize, f64)>; PACKET_SIZE]
+    where
+        F: FnMut(usize, &RayPacket) -> [Option<f64>; PACKET_SIZE],
+    {
+        let mut closest = [None; PACKET_SIZE];
+        for &i in &self.unbounded {
+            update_closest(&mut closest, i, &intersect(i, packet));
+        }
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        // rays are coherent, the first one decides the order of children
+        let first = match packet.active.iter().position(|&active| active) {
+            Some(lane) => &packet.rays[lane],
+            None => return closest,
+        };
+        let direction_negative = [
+            first.direction.x < 0.0,
+            first.direction.y < 0.0,
+            first.direction.z < 0.0,
+        ];
+        let mut stack = [0; STACK_SIZE];
+        let mut stack_len = 1;
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node_index = stack[stack_len];
+            let node = &self.nodes[node_index];
+            let max_distance: [f64; PACKET_SIZE] =
+                std::array::from_fn(|lane| match closest[lane] {
+                    Some((_, d)) => d,
+                    None => f64::INFINITY,
+                });
+            let hits = packet.intersect_aabb(&node.bounds, &max_distance);
+            if !hits.contains(&true) {
+                continue;
+            }
+
+            if node.count > 0 {
+                // lanes missing the leaf skip its primitives
+                let leaf_packet = if hits == packet.active {
+                    None
+                } else {
+                    Some(packet.masked(&hits))
+                };
+                let leaf_packet = leaf_packet.as_ref().unwrap_or(packet);
+                for &i in &self.indices[node.first..node.first + node.count] {
+                    update_closest(&mut closest, i, &intersect(i, leaf_packet));
+                }
+            } else if direction_negative[node.axis] {
+                stack[stack_len] = node_index + 1;
+                stack[stack_len + 1] = node.first;
+                stack_len += 2;
+            } else {
+                stack[stack_len] = node.first;
+                stack[stack_len + 1] = node_index + 1;
+                stack_len += 2;
+            }
+        }
+
+        closest
+    }
+
+    // returns true if occludes is true for any primitive whose bounds
+    // the ray meets closer than max_distance
+    pub fn any_hit<F>(&self, ray: &Ray, max_distance: f64, mut occludes: F) -> bool
+    where
+        F: FnMut(usize) -> bool,
+    {
+        if self.unbounded.iter().any(|&i| occludes(i)) {
+            return true;
+        }
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let mut stack = [0; STACK_SIZE];
+        let mut stack_len = 1;
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node_index = stack[stack_len];
+            let node = &self.nodes[node_index];
+            if node.bounds.intersect(ray, max_distance).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                if self.indices[node.first..node.first + node.count]
+                    .iter()
+                    .any(|&i| occludes(i))
+                {
+                    return true;
+                }
+            } else {
+                stack[stack_len] = node.first;
+                stack[stack_len + 1] = node_index + 1;
+                stack_len += 2;
+            }
+        }
+
+        false
+    }
+}
+
+impl Accelerator for Bvh {
+    fn closest(
+        &self,
+        ray: &Ray,
+        intersect: &mut dyn FnMut(usize) -> Option<f64>,
+    ) -> Option<(usize, f64)> {
+        Bvh::closest(self, ray, intersect)
+    }
+
+    fn any_hit(
+        &self,
+        ray: &Ray,
+        max_distance: f64,
+        occludes: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        Bvh::any_hit(self, ray, max_distance, occludes)
+    }
+
+    fn closest_packet(
+        &self,
+        packet: &RayPacket,
+        intersect: &mut dyn FnMut(usize, &RayPacket) -> [Option<f64>; PACKET_SIZE],
+    ) -> Option<[Option<(usize, f64)>; PACKET_SIZE]> {
+        Some(Bvh::closest_packet(self, packet, intersect))
+    }
+}
+
+fn axis_value(point: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+fn bin_index(value: f64, min: f64, extent: f64) -> usize {
+    (((value - min) / extent * BINS as f64) as usize).min(BINS - 1)
+}
+
+// returns best (axis, bin, cost) split, primitives with
+// centroid in bins below bin go to the left child
+fn find_split(
+    bounds: &[Aabb],
+    centroids: &[Point],
+    items: &[usize],
+    node_bounds: &Aabb,
+    centroid_bounds: &Aabb,
+) -> Option<(usize, usize, f64)> {
+    let node_area = node_bounds.surface_area().max(f64::MIN_POSITIVE);
+    let mut best: Option<(usize, usize, f64)> = None;
+
+    for axis in 0..3 {
+        let min = axis_value(&centroid_bounds.min, axis);
+        let extent = axis_value(&centroid_bounds.max, axis) - min;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut bin_bounds = [Aabb::empty(); BINS];
+        let mut bin_counts = [0usize; BINS];
+        for &i in items {
+            let b = bin_index(axis_value(&centroids[i], axis), min, extent);
+            bin_bounds[b] = bin_bounds[b].union(&bounds[i]);
+            bin_counts[b] += 1;
+        }
+
+        // sweep from the right to get area and count of every right side
+        let mut right_area = [0.0; BINS];
+        let mut right_count = [0usize; BINS];
+        let mut acc_bounds = Aabb::empty();
+        let mut acc_count = 0;
+        for b in (1..BINS).rev() {
+            acc_bounds = acc_bounds.union(&bin_bounds[b]);
+            acc_count += bin_counts[b];
+            right_area[b] = acc_bounds.surface_area();
+            right_count[b] = acc_count;
+        }
+
+        let mut left_bounds = Aabb::empty();
+        let mut left_count = 0;
+        for b in 1..BINS {
+            left_bounds = left_bounds.union(&bin_bounds[b - 1]);
+            left_count += bin_counts[b - 1];
+            if left_count == 0 || right_count[b] == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST
+                + (left_count as f64 * left_bounds.surface_area()
+                    + right_count[b] as f64 * right_area[b])
+                    / node_area;
+            if best.is_none_or(|(_, _, c)| cost < c) {
+                best = Some((axis, b, cost));
+            }
+        }
+    }
+
+    best
+}
@@ -0,0 +1,199 @@
+use crate::point::Point;
+use crate::rendering::Ray;
+use crate::sampling::{sample_unit_disk, Rng};
+use crate::vector3::Vector3;
+use std::f64::consts::PI;
+
+// Projection maps a point on the image onto a ray in camera space,
+// camera space has x to the right, y up and the camera looks down -z
+pub trait Projection {
+    // u and v are image coordinates in 0.0…1.0 from the top left corner,
+    // aspect_ratio is width / height of the image;
+    // returns ray origin and direction or None if the point is not covered by the lens,
+    // the thin lens focuses at origin + direction * focal_distance
+    fn project(&self, u: f64, v: f64, aspect_ratio: f64) -> Option<(Vector3, Vector3)>;
+}
+
+// returns sensor coordinates where the shorter image side spans (-1.0…1.0)
+// and the longer side is stretched by the aspect ratio
+fn sensor_coords(u: f64, v: f64, aspect_ratio: f64) -> (f64, f64) {
+    let (aspect_x, aspect_y) = if aspect_ratio >= 1.0 {
+        (aspect_ratio, 1.0)
+    } else {
+        (1.0, aspect_ratio.recip())
+    };
+
+    ((u * 2.0 - 1.0) * aspect_x, (1.0 - v * 2.0) * aspect_y)
+}
+
+// pinhole camera, rays fan out from the camera position through the sensor
+pub struct Perspective {
+    // field of view in degrees across the shorter side of the image,
+    // vertical for landscape and horizontal for portrait images
+    pub fov: f64,
+}
+
+impl Projection for Perspective {
+    fn project(&self, u: f64, v: f64, aspect_ratio: f64) -> Option<(Vector3, Vector3)> {
+        // the sensor is 1 unit from the camera,
+        // so fov always spans the shorter side
+        let (sensor_x, sensor_y) = sensor_coords(u, v, aspect_ratio);
+        let fov_adjustment = (self.fov.to_radians() / 2.0).tan();
+
+        Some((
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(sensor_x * fov_adjustment, sensor_y * fov_adjustment, -1.0),
+        ))
+    }
+}
+
+// all rays are parallel to the view direction
+pub struct Orthographic {
+    // shorter side of the sensor in world units
+    pub size: f64,
+}
+
+impl Projection for Orthographic {
+    fn project(&self, u: f64, v: f64, aspect_ratio: f64) -> Option<(Vector3, Vector3)> {
+        // the sensor is in the camera plane and rays leave it in parallel
+        let (sensor_x, sensor_y) = sensor_coords(u, v, aspect_ratio);
+        let half_size = self.size / 2.0;
+
+        Some((
+            Vector3::new(sensor_x * half_size, sensor_y * half_size, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+        ))
+    }
+}
+
+// 360° panorama, longitude spans the image width and latitude the height,
+// the view direction is in the center of the image
+pub struct Equirectangular;
+
+impl Projection for Equirectangular {
+    fn project(&self, u: f64, v: f64, _aspect_ratio: f64) -> Option<(Vector3, Vector3)> {
+        let longitude = (u - 0.5) * 2.0 * PI;
+        let latitude = (0.5 - v) * PI;
+
+        Some((
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(
+                latitude.cos() * longitude.sin(),
+                latitude.sin(),
+                -latitude.cos() * longitude.cos(),
+            ),
+        ))
+    }
+}
+
+// equidistant fisheye lens, the angle from the view direction grows linearly
+// with the distance from the image center, the image circle touches the shorter side
+pub struct Fisheye {
+    // field of view in degrees across the image circle, up to 360.0
+    pub fov: f64,
+}
+
+impl Projection for Fisheye {
+    fn project(&self, u: f64, v: f64, aspect_ratio: f64) -> Option<(Vector3, Vector3)> {
+        let (sensor_x, sensor_y) = sensor_coords(u, v, aspect_ratio);
+        let r = (sensor_x * sensor_x + sensor_y * sensor_y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = r * self.fov.to_radians() / 2.0;
+        let phi = sensor_y.atan2(sensor_x);
+
+        Some((
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                -theta.cos(),
+            ),
+        ))
+    }
+}
+
+pub struct Camera {
+    pub position: Point,
+    pub look_at: Point,
+    // approximate up direction, it does not need to be orthogonal to the view direction
+    pub up: Vector3,
+    pub projection: Box<dyn Projection + Sync + Send>,
+    // radius of the lens, 0.0 gives a pinhole camera with everything in focus,
+    // the blur gets smooth with several samples per pixel
+    pub aperture: f64,
+    // distance from the camera to the plane in perfect focus
+    pub focal_distance: f64,
+}
+
+impl Camera {
+    pub fn new(
+        position: Point,
+        look_at: Point,
+        up: Vector3,
+        projection: Box<dyn Projection + Sync + Send>,
+    ) -> Camera {
+        Camera {
+            position,
+            look_at,
+            up,
+            projection,
+            aperture: 0.0,
+            focal_distance: (look_at - position).length(),
+        }
+    }
+
+    // returns orthonormal basis (right, up, back) of the camera,
+    // the camera looks in the direction opposite to back
+    pub fn basis(&self) -> (Vector3, Vector3, Vector3) {
+        let back = (self.position - self.look_at).normalize();
+        let right = self.up.cross(&back);
+        // up along the view direction leaves the roll undefined, any right will do
+        let right = if right.length() > 1e-9 * self.up.length() {
+            right.normalize()
+        } else {
+            back.orthonormal_basis().0
+        };
+        let up = back.cross(&right);
+
+        (right, up, back)
+    }
+
+    // returns world space ray through the image point (u, v), see Projection::project;
+    // basis is the result of Camera::basis, computed once for many rays
+    pub fn ray(
+        &self,
+        basis: &(Vector3, Vector3, Vector3),
+        u: f64,
+        v: f64,
+        aspect_ratio: f64,
+        rng: &mut Rng,
+    ) -> Option<Ray> {
+        let (local_origin, local_direction) = self.projection.project(u, v, aspect_ratio)?;
+
+        // orient the projection with the camera basis
+        let &(right, up, back) = basis;
+        let to_world = |local: Vector3| right * local.x + up * local.y + back * local.z;
+        let origin = self.position + to_world(local_origin);
+        let direction = to_world(local_direction);
+        if self.aperture <= 0.0 {
+            return Some(Ray {
+                origin,
+                direction: direction.normalize(),
+            });
+        }
+
+        // thin lens: rays from every point of the lens disk
+        // converge on the focal plane, so only it stays sharp
+        let focus_point = origin + direction * self.focal_distance;
+        let (lens_x, lens_y) = sample_unit_disk(rng.next_f64(), rng.next_f64());
+        let lens_point = origin + (right * lens_x + up * lens_y) * self.aperture;
+
+        Some(Ray {
+            origin: lens_point,
+            direction: (focus_point - lens_point).normalize(),
+        })
+    }
+}
@@ -0,0 +1,136 @@
+use crate::aabb::Aabb;
+use crate::point::Point;
+use crate::rendering::{Intersectable, Intersection, Ray, Span};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    // left without right
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(&self, left: bool, right: bool) -> bool {
+        match self {
+            CsgOperation::Union => left || right,
+            CsgOperation::Intersection => left && right,
+            CsgOperation::Difference => left && !right,
+        }
+    }
+}
+
+// Csg combines two solids, e.g. lenses from intersected spheres or bowls
+// from a box without a sphere; surfaces cut out by the right object
+// of a difference keep its material
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Box<dyn Intersectable + Sync + Send>,
+    pub right: Box<dyn Intersectable + Sync + Send>,
+}
+
+impl Csg {
+    // panics if an operand is not a solid, see Intersectable::spans
+    pub fn new(
+        operation: CsgOperation,
+        left: Box<dyn Intersectable + Sync + Send>,
+        right: Box<dyn Intersectable + Sync + Send>,
+    ) -> Csg {
+        assert!(
+            left.is_solid() && right.is_solid(),
+            "CSG operands must be solids"
+        );
+        Csg {
+            operation,
+            left,
+            right,
+        }
+    }
+
+    // returns the first boundary of the combined solid in front of the origin
+    fn first_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.combined_spans(ray)
+            .into_iter()
+            .flat_map(|span| [span.enter, span.exit])
+            .find(|hit| hit.distance >= 0.0)
+    }
+
+    fn combined_spans(&self, ray: &Ray) -> Vec<Span<'_>> {
+        // sweep over boundaries of both operands tracking which one the ray is in
+        let mut events: Vec<(Intersection, bool)> = vec![];
+        for (object, is_left) in [(&self.left, true), (&self.right, false)] {
+            // operands set without new may have no inside, they add no boundaries
+            for span in object.spans(ray).unwrap_or_default() {
+                events.push((span.enter, is_left));
+                events.push((span.exit, is_left));
+            }
+        }
+        events.sort_by(|a, b| a.0.distance.partial_cmp(&b.0.distance).unwrap());
+
+        let mut spans = vec![];
+        let mut enter: Option<Intersection> = None;
+        let (mut in_left, mut in_right) = (false, false);
+        for (mut hit, is_left) in events {
+            let was_inside = self.operation.inside(in_left, in_right);
+            if is_left {
+                in_left = !in_left;
+            } else {
+                in_right = !in_right;
+            }
+            if self.operation.inside(in_left, in_right) == was_inside {
+                continue;
+            }
+            // surface of the subtracted object faces into it
+            if self.operation == CsgOperation::Difference && !is_left {
+                hit.normal = -hit.normal;
+            }
+            match enter.take() {
+                None => enter = Some(hit),
+                Some(enter) => spans.push(Span { enter, exit: hit }),
+            }
+        }
+
+        spans
+    }
+}
+
+impl Intersectable for Csg {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.first_hit(ray).map(|hit| hit.distance)
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.first_hit(ray)
+    }
+
+    fn bounds(&self) -> Aabb {
+        let left = self.left.bounds();
+        match self.operation {
+            CsgOperation::Union => left.union(&self.right.bounds()),
+            CsgOperation::Intersection => {
+                let right = self.right.bounds();
+                Aabb::new(
+                    Point::new(
+                        left.min.x.max(right.min.x),
+                        left.min.y.max(right.min.y),
+                        left.min.z.max(right.min.z),
+                    ),
+                    Point::new(
+                        left.max.x.min(right.max.x),
+                        left.max.y.min(right.max.y),
+                        left.max.z.min(right.max.z),
+                    ),
+                )
+            }
+            CsgOperation::Difference => left,
+        }
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span<'_>>> {
+        Some(self.combined_spans(ray))
+    }
+}
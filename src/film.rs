@@ -0,0 +1,127 @@
+use crate::sampling::Filter;
+use crate::scene::Color;
+use image::*;
+
+// pixels whose weights sum to less than this fraction of the sum of their
+// absolute values, e.g. when negative lobes of the Mitchell filter cancel
+// them out, use the box filtered mean
+const MIN_WEIGHT_RATIO: f32 = 0.1;
+
+#[derive(Clone, Copy)]
+struct Pixel {
+    // weighted sum of sample colors, the sum of weights
+    // and the sum of their absolute values
+    color: Color,
+    weight: f32,
+    abs_weight: f32,
+    // sum and number of the samples inside the pixel
+    box_color: Color,
+    box_count: u32,
+}
+
+// Film accumulates filtered samples for a rectangle of the image,
+// every pixel keeps the weighted sum of colors and the sum of weights
+pub struct Film {
+    start_x: u32,
+    start_y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<Pixel>,
+}
+
+impl Film {
+    pub fn new(start_x: u32, start_y: u32, width: u32, height: u32) -> Film {
+        let black = Color {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+        };
+        let pixel = Pixel {
+            color: black,
+            weight: 0.0,
+            abs_weight: 0.0,
+            box_color: black,
+            box_count: 0,
+        };
+        Film {
+            start_x,
+            start_y,
+            width,
+            height,
+            pixels: vec![pixel; (width * height) as usize],
+        }
+    }
+
+    // adds the sample at image position (x, y) in pixels to every
+    // pixel of the film whose center is within the filter radius
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Color, filter: &Filter) {
+        let radius = filter.radius();
+        let min_x = ((x - 0.5 - radius).ceil() as i64).max(i64::from(self.start_x));
+        let max_x =
+            ((x - 0.5 + radius).floor() as i64).min(i64::from(self.start_x + self.width) - 1);
+        let min_y = ((y - 0.5 - radius).ceil() as i64).max(i64::from(self.start_y));
+        let max_y =
+            ((y - 0.5 + radius).floor() as i64).min(i64::from(self.start_y + self.height) - 1);
+
+        for px in min_x..=max_x {
+            for py in min_y..=max_y {
+                let index =
+                    ((py as u32 - self.start_y) * self.width + (px as u32 - self.start_x)) as usize;
+                let pixel = &mut self.pixels[index];
+                if x.floor() as i64 == px && y.floor() as i64 == py {
+                    pixel.box_color = pixel.box_color + color;
+                    pixel.box_count += 1;
+                }
+                let weight = filter.weight(px as f64 + 0.5 - x, py as f64 + 0.5 - y) as f32;
+                if weight == 0.0 {
+                    continue;
+                }
+                pixel.color = pixel.color + color * weight;
+                pixel.weight += weight;
+                pixel.abs_weight += weight.abs();
+            }
+        }
+    }
+
+    // adds samples accumulated by the other film to the pixels
+    // both films cover
+    pub fn merge(&mut self, other: &Film) {
+        let min_x = self.start_x.max(other.start_x);
+        let max_x = (self.start_x + self.width).min(other.start_x + other.width);
+        let min_y = self.start_y.max(other.start_y);
+        let max_y = (self.start_y + self.height).min(other.start_y + other.height);
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let other_pixel = other.pixels
+                    [((y - other.start_y) * other.width + (x - other.start_x)) as usize];
+                let pixel = &mut self.pixels
+                    [((y - self.start_y) * self.width + (x - self.start_x)) as usize];
+                pixel.color = pixel.color + other_pixel.color;
+                pixel.weight += other_pixel.weight;
+                pixel.abs_weight += other_pixel.abs_weight;
+                pixel.box_color = pixel.box_color + other_pixel.box_color;
+                pixel.box_count += other_pixel.box_count;
+            }
+        }
+    }
+
+    pub fn to_image(&self) -> DynamicImage {
+        let mut image = DynamicImage::new_rgb8(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pixel = self.pixels[(y * self.width + x) as usize];
+                let color =
+                    if pixel.weight > 0.0 && pixel.weight >= MIN_WEIGHT_RATIO * pixel.abs_weight {
+                        pixel.color * pixel.weight.recip()
+                    } else if pixel.box_count > 0 {
+                        pixel.box_color * (pixel.box_count as f32).recip()
+                    } else {
+                        pixel.box_color
+                    };
+                image.put_pixel(x, y, color.clamp().to_rgba());
+            }
+        }
+
+        image
+    }
+}
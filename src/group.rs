@@ -0,0 +1,136 @@
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::matrix4::Matrix4;
+use crate::rendering::{Intersectable, Intersection, Ray};
+use crate::transform::{object_ray, world_bounds, world_intersection};
+
+// Group is a node of a scene hierarchy holding children in its own space,
+// optionally placed by a transform; rays missing the cached bounds of
+// the children skip the whole group
+pub struct Group {
+    children: Vec<Box<dyn Intersectable + Sync + Send>>,
+    // group to world space and back
+    transform: Option<(Matrix4, Matrix4)>,
+    // bounds of the children in group space
+    bounds: Aabb,
+    // hierarchy over children, see Group::build_bvh
+    bvh: Option<Bvh>,
+}
+
+impl Default for Group {
+    fn default() -> Group {
+        Group::new()
+    }
+}
+
+impl Group {
+    pub fn new() -> Group {
+        Group {
+            children: vec![],
+            transform: None,
+            bounds: Aabb::empty(),
+            bvh: None,
+        }
+    }
+
+    // panics if the matrix is not invertible
+    pub fn with_transform(matrix: Matrix4) -> Group {
+        let inverse = matrix
+            .inverse()
+            .expect("transform matrix is not invertible");
+        Group {
+            transform: Some((matrix, inverse)),
+            ..Group::new()
+        }
+    }
+
+    pub fn add(&mut self, child: Box<dyn Intersectable + Sync + Send>) {
+        self.bounds = self.bounds.union(&child.bounds());
+        self.children.push(child);
+        // the hierarchy does not know the new child
+        self.bvh = None;
+    }
+
+    pub fn children(&self) -> &[Box<dyn Intersectable + Sync + Send>] {
+        &self.children
+    }
+
+    // builds bounding volume hierarchy over the children,
+    // worth it for groups with many children
+    pub fn build_bvh(&mut self) {
+        let bounds: Vec<Aabb> = self.children.iter().map(|c| c.bounds()).collect();
+        self.bvh = Some(Bvh::new(&bounds));
+    }
+
+    // returns index of the closest child and distance to it in group space
+    fn closest(&self, ray: &Ray) -> Option<(usize, f64)> {
+        self.bounds.intersect(ray, f64::INFINITY)?;
+        match self.bvh {
+            Some(ref bvh) => bvh.closest(ray, |i| self.children[i].intersect(ray)),
+            None => self
+                .children
+                .iter()
+                .enumerate()
+                .filter_map(|(i, c)| c.intersect(ray).map(|d| (i, d)))
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap()),
+        }
+    }
+
+    // returns true if any child is hit closer than max_distance in group space
+    fn any_child_occludes(&self, ray: &Ray, max_distance: f64) -> bool {
+        if self.bounds.intersect(ray, max_distance).is_none() {
+            return false;
+        }
+        match self.bvh {
+            Some(ref bvh) => bvh.any_hit(ray, max_distance, |i| {
+                self.children[i].occludes(ray, max_distance)
+            }),
+            None => self.children.iter().any(|c| c.occludes(ray, max_distance)),
+        }
+    }
+}
+
+impl Intersectable for Group {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        match self.transform {
+            Some((_, ref inverse)) => {
+                let (group_ray, scale) = object_ray(inverse, ray);
+                self.closest(&group_ray).map(|(_, d)| d / scale)
+            }
+            None => self.closest(ray).map(|(_, d)| d),
+        }
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        match self.transform {
+            Some((_, ref inverse)) => {
+                let (group_ray, scale) = object_ray(inverse, ray);
+                let (i, _) = self.closest(&group_ray)?;
+                self.children[i]
+                    .intersection(&group_ray)
+                    .map(|intersection| world_intersection(inverse, intersection, scale))
+            }
+            None => {
+                let (i, _) = self.closest(ray)?;
+                self.children[i].intersection(ray)
+            }
+        }
+    }
+
+    fn occludes(&self, ray: &Ray, max_distance: f64) -> bool {
+        match self.transform {
+            Some((_, ref inverse)) => {
+                let (group_ray, scale) = object_ray(inverse, ray);
+                self.any_child_occludes(&group_ray, max_distance * scale)
+            }
+            None => self.any_child_occludes(ray, max_distance),
+        }
+    }
+
+    fn bounds(&self) -> Aabb {
+        match self.transform {
+            Some((ref matrix, _)) => world_bounds(matrix, &self.bounds),
+            None => self.bounds,
+        }
+    }
+}
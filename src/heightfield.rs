@@ -0,0 +1,196 @@
+use crate::aabb::Aabb;
+use crate::point::Point;
+use crate::rendering::{intersect_triangle, Intersectable, Intersection, Ray};
+use crate::scene::{Material, TextureCoords};
+use crate::vector3::Vector3;
+
+use image::DynamicImage;
+
+// Heightfield is a terrain over a regular grid in the xz plane, every pixel of
+// a grayscale image gives the height of one grid vertex, cells between
+// vertices are split into two triangles
+pub struct Heightfield {
+    // corner of the grid at the first pixel and zero height
+    pub origin: Point,
+    // extent of the grid along x and z, y is the height of the white pixel
+    pub size: Vector3,
+    // number of vertices along x and z
+    pub columns: usize,
+    pub rows: usize,
+    // vertices row by row
+    pub positions: Vec<Point>,
+    pub normals: Vec<Vector3>,
+    pub material: Material,
+    pub bounds: Aabb,
+}
+
+impl Heightfield {
+    // image x goes along x and image y along z, panics for images
+    // smaller than 2x2 pixels
+    pub fn from_image(
+        image: &DynamicImage,
+        origin: Point,
+        size: Vector3,
+        material: Material,
+    ) -> Heightfield {
+        let luma = image.to_luma();
+        let (columns, rows) = (luma.width() as usize, luma.height() as usize);
+        assert!(
+            columns >= 2 && rows >= 2,
+            "heightfield needs at least 2x2 pixels"
+        );
+
+        let cell_x = size.x / (columns - 1) as f64;
+        let cell_z = size.z / (rows - 1) as f64;
+        let height = |ix: usize, iz: usize| {
+            f64::from(luma.get_pixel(ix as u32, iz as u32).data[0]) / 255.0 * size.y
+        };
+
+        let mut positions = Vec::with_capacity(columns * rows);
+        let mut normals = Vec::with_capacity(columns * rows);
+        for iz in 0..rows {
+            for ix in 0..columns {
+                positions.push(Point::new(
+                    origin.x + ix as f64 * cell_x,
+                    origin.y + height(ix, iz),
+                    origin.z + iz as f64 * cell_z,
+                ));
+                // central differences, one-sided at the border
+                let (x0, x1) = (ix.saturating_sub(1), (ix + 1).min(columns - 1));
+                let (z0, z1) = (iz.saturating_sub(1), (iz + 1).min(rows - 1));
+                let slope_x = (height(x1, iz) - height(x0, iz)) / ((x1 - x0) as f64 * cell_x);
+                let slope_z = (height(ix, z1) - height(ix, z0)) / ((z1 - z0) as f64 * cell_z);
+                normals.push(Vector3::new(-slope_x, 1.0, -slope_z).normalize());
+            }
+        }
+
+        Heightfield {
+            origin,
+            size,
+            columns,
+            rows,
+            bounds: Aabb::from_points(&positions),
+            positions,
+            normals,
+            material,
+        }
+    }
+
+    // returns vertex indices of the two triangles of the cell,
+    // both counter-clockwise when looking from above
+    fn cell_triangles(&self, ix: usize, iz: usize) -> [[usize; 3]; 2] {
+        let a = iz * self.columns + ix;
+        let b = a + 1;
+        let c = a + self.columns;
+        let d = c + 1;
+        [[a, c, b], [b, c, d]]
+    }
+
+    // returns the closest triangle hit in the cell as in intersect_triangle
+    fn intersect_cell(
+        &self,
+        ray: &Ray,
+        ix: usize,
+        iz: usize,
+    ) -> Option<([usize; 3], f64, f64, f64)> {
+        self.cell_triangles(ix, iz)
+            .iter()
+            .filter_map(|&triangle| {
+                let vertices = triangle.map(|i| self.positions[i]);
+                intersect_triangle(ray, &vertices)
+                    .map(|(distance, u, v)| (triangle, distance, u, v))
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+    }
+
+    // walks the cells under the ray front to back, the first cell with
+    // a hit holds the closest one as cells do not overlap
+    fn closest_hit(&self, ray: &Ray) -> Option<([usize; 3], f64, f64, f64)> {
+        let (enter, exit) = self.bounds.intersect(ray, f64::INFINITY)?;
+        let cell_x = self.size.x / (self.columns - 1) as f64;
+        let cell_z = self.size.z / (self.rows - 1) as f64;
+        let start = ray.origin + ray.direction * enter;
+        let mut ix = cell_index(start.x, self.origin.x, cell_x, self.columns);
+        let mut iz = cell_index(start.z, self.origin.z, cell_z, self.rows);
+        let (step_x, mut next_x, delta_x) =
+            grid_step(ray.origin.x, ray.direction.x, self.origin.x, cell_x, ix);
+        let (step_z, mut next_z, delta_z) =
+            grid_step(ray.origin.z, ray.direction.z, self.origin.z, cell_z, iz);
+
+        loop {
+            if let Some(hit) = self.intersect_cell(ray, ix as usize, iz as usize) {
+                return Some(hit);
+            }
+            if next_x < next_z {
+                if next_x > exit {
+                    return None;
+                }
+                ix += step_x;
+                next_x += delta_x;
+            } else {
+                if next_z > exit {
+                    return None;
+                }
+                iz += step_z;
+                next_z += delta_z;
+            }
+            if ix < 0 || ix as usize >= self.columns - 1 || iz < 0 || iz as usize >= self.rows - 1 {
+                return None;
+            }
+        }
+    }
+}
+
+// returns cell containing the coordinate clamped to the grid
+fn cell_index(value: f64, origin: f64, cell_size: f64, vertices: usize) -> isize {
+    (((value - origin) / cell_size).floor().max(0.0) as isize).min(vertices as isize - 2)
+}
+
+// returns step to the neighbour cell, distance to the first cell
+// boundary along the axis and distance between boundaries
+fn grid_step(
+    origin: f64,
+    direction: f64,
+    grid_origin: f64,
+    cell_size: f64,
+    index: isize,
+) -> (isize, f64, f64) {
+    if direction > 0.0 {
+        let boundary = grid_origin + (index + 1) as f64 * cell_size;
+        (1, (boundary - origin) / direction, cell_size / direction)
+    } else if direction < 0.0 {
+        let boundary = grid_origin + index as f64 * cell_size;
+        (-1, (boundary - origin) / direction, -cell_size / direction)
+    } else {
+        (0, f64::INFINITY, f64::INFINITY)
+    }
+}
+
+impl Intersectable for Heightfield {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.closest_hit(ray).map(|(_, distance, _, _)| distance)
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let ([i0, i1, i2], distance, u, v) = self.closest_hit(ray)?;
+        let w = 1.0 - u - v;
+        let normal =
+            (self.normals[i0] * w + self.normals[i1] * u + self.normals[i2] * v).normalize();
+        let hit_point = ray.origin + ray.direction * distance;
+        let texture_coords = TextureCoords {
+            x: ((hit_point.x - self.origin.x) / self.size.x) as f32,
+            y: ((hit_point.z - self.origin.z) / self.size.z) as f32,
+        };
+
+        Some(Intersection::new(
+            distance,
+            normal,
+            texture_coords,
+            &self.material,
+        ))
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
@@ -0,0 +1,173 @@
+use crate::aabb::Aabb;
+use crate::point::Point;
+use crate::rendering::{spherical_texture_coords, Intersectable, Intersection, Ray, Surface};
+use crate::scene::{Material, TextureCoords};
+use crate::sdf::gradient;
+use crate::vector3::Vector3;
+
+// number of steps across the bounds diagonal when searching for a sign change
+const BRACKET_STEPS: f64 = 256.0;
+const REFINE_ITERATIONS: u32 = 40;
+
+// scalar field, larger values are inside
+pub type FieldFn = Box<dyn Fn(&Point) -> f64 + Sync + Send>;
+
+// ImplicitSurface is the level set where the field equals the threshold;
+// the ray is sampled in steps to bracket a sign change which is then
+// refined, so features thinner than the step may be missed
+pub struct ImplicitSurface {
+    pub field: FieldFn,
+    pub threshold: f64,
+    // bounds must contain the whole surface
+    pub bounds: Aabb,
+    // distance between samples along the ray
+    pub step: f64,
+    pub material: Material,
+}
+
+impl ImplicitSurface {
+    pub fn new(
+        field: FieldFn,
+        threshold: f64,
+        bounds: Aabb,
+        material: Material,
+    ) -> ImplicitSurface {
+        let diagonal = (bounds.max - bounds.min).length();
+        ImplicitSurface {
+            field,
+            threshold,
+            bounds,
+            step: diagonal / BRACKET_STEPS,
+            material,
+        }
+    }
+
+    // blobby object, the balls melt together where their fields overlap
+    // and the threshold is between 0 and the strength of the balls
+    pub fn metaballs(balls: Vec<Metaball>, threshold: f64, material: Material) -> ImplicitSurface {
+        let bounds = balls.iter().fold(Aabb::empty(), |bounds, ball| {
+            let r = Vector3::new(ball.radius, ball.radius, ball.radius);
+            bounds.union(&Aabb::new(ball.center - r, ball.center + r))
+        });
+        let smallest = balls
+            .iter()
+            .map(|ball| ball.radius)
+            .fold(f64::INFINITY, f64::min);
+        let mut surface = ImplicitSurface::new(
+            Box::new(move |p| balls.iter().map(|ball| ball.field(p)).sum()),
+            threshold,
+            bounds,
+            material,
+        );
+        // small balls need finer steps not to be stepped over
+        surface.step = surface.step.min(smallest / 8.0);
+        surface
+    }
+
+    fn value(&self, ray: &Ray, t: f64) -> f64 {
+        (self.field)(&(ray.origin + ray.direction * t)) - self.threshold
+    }
+}
+
+pub struct Metaball {
+    pub center: Point,
+    // the field falls to zero at the radius
+    pub radius: f64,
+    pub strength: f64,
+}
+
+impl Metaball {
+    pub fn new(center: Point, radius: f64, strength: f64) -> Metaball {
+        Metaball {
+            center,
+            radius,
+            strength,
+        }
+    }
+
+    // smooth polynomial falloff with compact support
+    pub fn field(&self, point: &Point) -> f64 {
+        let r2 = (*point - self.center).norm() / (self.radius * self.radius);
+        if r2 >= 1.0 {
+            0.0
+        } else {
+            let falloff = 1.0 - r2;
+            self.strength * falloff * falloff * falloff
+        }
+    }
+}
+
+impl Intersectable for ImplicitSurface {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (enter, exit) = self.bounds.intersect(ray, f64::INFINITY)?;
+        if self.step <= 0.0 {
+            return None;
+        }
+
+        // bracket the first sign change
+        let (mut t0, mut v0) = (enter, self.value(ray, enter));
+        let (mut t1, mut v1) = loop {
+            if t0 >= exit {
+                return None;
+            }
+            let t = (t0 + self.step).min(exit);
+            let v = self.value(ray, t);
+            if v0.signum() != v.signum() {
+                break (t, v);
+            }
+            t0 = t;
+            v0 = v;
+        };
+
+        // refine with the Illinois variant of regula falsi
+        let mut side = 0;
+        for _ in 0..REFINE_ITERATIONS {
+            let t = (t0 * v1 - t1 * v0) / (v1 - v0);
+            let v = self.value(ray, t);
+            if v == 0.0 || (t1 - t0).abs() < 1e-12 * t.max(1.0) {
+                return Some(t);
+            }
+            if v.signum() == v0.signum() {
+                t0 = t;
+                v0 = v;
+                if side == -1 {
+                    v1 *= 0.5;
+                }
+                side = -1;
+            } else {
+                t1 = t;
+                v1 = v;
+                if side == 1 {
+                    v0 *= 0.5;
+                }
+                side = 1;
+            }
+        }
+
+        Some((t0 * v1 - t1 * v0) / (v1 - v0))
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self))
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+impl Surface for ImplicitSurface {
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        // the field decreases outwards
+        -gradient(&self.field, hit_point).normalize()
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn texture_coords(&self, point: &Point) -> TextureCoords {
+        spherical_texture_coords(&self.surface_normal(point))
+    }
+}
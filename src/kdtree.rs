@@ -0,0 +1,385 @@
+use crate::aabb::Aabb;
+use crate::accelerator::Accelerator;
+use crate::point::Point;
+use crate::rendering::Ray;
+
+// costs of the surface area heuristic
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECT_COST: f64 = 80.0;
+// splits leaving one side empty are preferred by this fraction
+const EMPTY_BONUS: f64 = 0.5;
+// nodes with more primitives are split even if the heuristic prefers a leaf
+const MAX_LEAF_SIZE: usize = 16;
+// limit of the depth from the heuristic, it bounds the traversal stacks
+const MAX_DEPTH: usize = 64;
+
+enum Node {
+    // primitives are indices[first..first + count]
+    Leaf {
+        first: usize,
+        count: usize,
+    },
+    // the child below the split follows the node, above is the index of the other
+    Interior {
+        axis: usize,
+        split: f64,
+        above: usize,
+    },
+}
+
+// KdTree splits space by axis-aligned planes chosen with the surface area
+// heuristic, primitives crossing a plane are referenced on both sides;
+// primitives with infinite bounds are kept aside and tested for every ray
+pub struct KdTree {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+    bounds: Aabb,
+}
+
+impl KdTree {
+    pub fn new(bounds: &[Aabb]) -> KdTree {
+        // primitives with empty bounds can not be hit
+        let items: Vec<usize> = (0..bounds.len())
+            .filter(|&i| bounds[i].is_finite() && !bounds[i].is_empty())
+            .collect();
+        let mut tree = KdTree {
+            nodes: vec![],
+            indices: vec![],
+            unbounded: (0..bounds.len())
+                .filter(|&i| !bounds[i].is_finite())
+                .collect(),
+            bounds: items
+                .iter()
+                .fold(Aabb::empty(), |b, &i| b.union(&bounds[i])),
+        };
+        if !items.is_empty() {
+            let max_depth =
+                ((8.0 + 1.3 * (items.len() as f64).log2()).round() as usize).min(MAX_DEPTH);
+            let node_bounds = tree.bounds;
+            tree.build(bounds, items, &node_bounds, max_depth);
+        }
+
+        tree
+    }
+
+    // builds node for the items and returns its index
+    fn build(
+        &mut self,
+        bounds: &[Aabb],
+        items: Vec<usize>,
+        node_bounds: &Aabb,
+        depth: usize,
+    ) -> usize {
+        let node_index = self.nodes.len();
+        let split = if depth == 0 || items.len() <= 1 {
+            None
+        } else {
+            find_split(bounds, &items, node_bounds)
+        };
+        let (axis, position) = match split {
+            Some((axis, position, cost))
+                if cost < INTERSECT_COST * items.len() as f64 || items.len() > MAX_LEAF_SIZE =>
+            {
+                (axis, position)
+            }
+            _ => {
+                self.nodes.push(Node::Leaf {
+                    first: self.indices.len(),
+                    count: items.len(),
+                });
+                self.indices.extend(items);
+                return node_index;
+            }
+        };
+
+        // flat primitives lying in the plane go below
+        let below: Vec<usize> = items
+            .iter()
+            .copied()
+            .filter(|&i| {
+                axis_value(&bounds[i].min, axis) < position
+                    || axis_value(&bounds[i].max, axis) <= position
+            })
+            .collect();
+        let above: Vec<usize> = items
+            .into_iter()
+            .filter(|&i| axis_value(&bounds[i].max, axis) > position)
+            .collect();
+        let (below_bounds, above_bounds) = split_bounds(node_bounds, axis, position);
+
+        self.nodes.push(Node::Interior {
+            axis,
+            split: position,
+            above: 0,
+        });
+        self.build(bounds, below, &below_bounds, depth - 1);
+        let above_index = self.build(bounds, above, &above_bounds, depth - 1);
+        if let Node::Interior { ref mut above, .. } = self.nodes[node_index] {
+            *above = above_index;
+        }
+
+        node_index
+    }
+
+    // returns index of the closest primitive and distance to it,
+    // intersect returns distance to the primitive with the given index
+    pub fn closest<F>(&self, ray: &Ray, mut intersect: F) -> Option<(usize, f64)>
+    where
+        F: FnMut(usize) -> Option<f64>,
+    {
+        let mut closest: Option<(usize, f64)> = None;
+        let mut test = |i: usize, closest: &mut Option<(usize, f64)>| {
+            if let Some(distance) = intersect(i) {
+                if closest.is_none_or(|(_, d)| distance < d) {
+                    *closest = Some((i, distance));
+                }
+            }
+        };
+
+        for &i in &self.unbounded {
+            test(i, &mut closest);
+        }
+        if self.nodes.is_empty() {
+            return closest;
+        }
+        let (mut t_min, mut t_max) = match self.bounds.intersect(ray, f64::INFINITY) {
+            Some(span) => span,
+            None => return closest,
+        };
+
+        // front to back traversal, stack holds far children with their ray spans,
+        // at most one for every level
+        let mut stack = [(0, 0.0, 0.0); MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut node_index = 0;
+        loop {
+            if closest.is_some_and(|(_, d)| d < t_min) {
+                break;
+            }
+            match self.nodes[node_index] {
+                Node::Interior { axis, split, above } => {
+                    let (first, second, t_plane) =
+                        self.children_order(ray, node_index, axis, split, above);
+                    match t_plane {
+                        Some(t) if t <= t_max && t > 0.0 => {
+                            if t < t_min {
+                                node_index = second;
+                            } else {
+                                stack[stack_len] = (second, t, t_max);
+                                stack_len += 1;
+                                node_index = first;
+                                t_max = t;
+                            }
+                        }
+                        _ => node_index = first,
+                    }
+                }
+                Node::Leaf { first, count } => {
+                    for &i in &self.indices[first..first + count] {
+                        test(i, &mut closest);
+                    }
+                    if stack_len == 0 {
+                        break;
+                    }
+                    stack_len -= 1;
+                    (node_index, t_min, t_max) = stack[stack_len];
+                }
+            }
+        }
+
+        closest
+    }
+
+    // returns true if occludes is true for any primitive in the leaves
+    // the ray passes closer than max_distance
+    pub fn any_hit<F>(&self, ray: &Ray, max_distance: f64, mut occludes: F) -> bool
+    where
+        F: FnMut(usize) -> bool,
+    {
+        if self.unbounded.iter().any(|&i| occludes(i)) {
+            return true;
+        }
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let (mut t_min, mut t_max) = match self.bounds.intersect(ray, max_distance) {
+            Some(span) => span,
+            None => return false,
+        };
+
+        let mut stack = [(0, 0.0, 0.0); MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut node_index = 0;
+        loop {
+            match self.nodes[node_index] {
+                Node::Interior { axis, split, above } => {
+                    let (first, second, t_plane) =
+                        self.children_order(ray, node_index, axis, split, above);
+                    match t_plane {
+                        Some(t) if t <= t_max && t > 0.0 => {
+                            if t < t_min {
+                                node_index = second;
+                            } else {
+                                stack[stack_len] = (second, t, t_max);
+                                stack_len += 1;
+                                node_index = first;
+                                t_max = t;
+                            }
+                        }
+                        _ => node_index = first,
+                    }
+                }
+                Node::Leaf { first, count } => {
+                    if self.indices[first..first + count]
+                        .iter()
+                        .any(|&i| occludes(i))
+                    {
+                        return true;
+                    }
+                    if stack_len == 0 {
+                        return false;
+                    }
+                    stack_len -= 1;
+                    (node_index, t_min, t_max) = stack[stack_len];
+                }
+            }
+        }
+    }
+
+    // returns the near and the far child of the interior node for the ray
+    // and distance to the split plane, None if the ray is parallel to it
+    fn children_order(
+        &self,
+        ray: &Ray,
+        node_index: usize,
+        axis: usize,
+        split: f64,
+        above: usize,
+    ) -> (usize, usize, Option<f64>) {
+        let origin = axis_value(&ray.origin, axis);
+        let direction = match axis {
+            0 => ray.direction.x,
+            1 => ray.direction.y,
+            _ => ray.direction.z,
+        };
+        let below_first = origin < split || (origin == split && direction <= 0.0);
+        let (first, second) = if below_first {
+            (node_index + 1, above)
+        } else {
+            (above, node_index + 1)
+        };
+        if direction == 0.0 {
+            (first, second, None)
+        } else {
+            (first, second, Some((split - origin) / direction))
+        }
+    }
+}
+
+impl Accelerator for KdTree {
+    fn closest(
+        &self,
+        ray: &Ray,
+        intersect: &mut dyn FnMut(usize) -> Option<f64>,
+    ) -> Option<(usize, f64)> {
+        KdTree::closest(self, ray, intersect)
+    }
+
+    fn any_hit(
+        &self,
+        ray: &Ray,
+        max_distance: f64,
+        occludes: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        KdTree::any_hit(self, ray, max_distance, occludes)
+    }
+}
+
+fn axis_value(point: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+fn split_bounds(bounds: &Aabb, axis: usize, position: f64) -> (Aabb, Aabb) {
+    let mut below_max = bounds.max;
+    let mut above_min = bounds.min;
+    match axis {
+        0 => {
+            below_max.x = position;
+            above_min.x = position;
+        }
+        1 => {
+            below_max.y = position;
+            above_min.y = position;
+        }
+        _ => {
+            below_max.z = position;
+            above_min.z = position;
+        }
+    }
+    (
+        Aabb::new(bounds.min, below_max),
+        Aabb::new(above_min, bounds.max),
+    )
+}
+
+// returns best (axis, position, cost) split plane among the bounds
+// edges of the items inside the node
+fn find_split(bounds: &[Aabb], items: &[usize], node_bounds: &Aabb) -> Option<(usize, f64, f64)> {
+    let node_area = node_bounds.surface_area().max(f64::MIN_POSITIVE);
+    let mut best: Option<(usize, f64, f64)> = None;
+
+    for axis in 0..3 {
+        let node_min = axis_value(&node_bounds.min, axis);
+        let node_max = axis_value(&node_bounds.max, axis);
+        if node_max <= node_min {
+            continue;
+        }
+
+        // (position, is end), ends sort before starts at the same position
+        let mut edges: Vec<(f64, bool)> = items
+            .iter()
+            .flat_map(|&i| {
+                [
+                    (axis_value(&bounds[i].min, axis), false),
+                    (axis_value(&bounds[i].max, axis), true),
+                ]
+            })
+            .collect();
+        edges.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap().then(b.1.cmp(&a.1)));
+
+        let mut below_count = 0;
+        let mut above_count = items.len();
+        for &(position, is_end) in &edges {
+            if is_end {
+                above_count -= 1;
+            }
+            if position > node_min && position < node_max {
+                let (below, above) = split_bounds(node_bounds, axis, position);
+                let bonus = if below_count == 0 || above_count == 0 {
+                    EMPTY_BONUS
+                } else {
+                    0.0
+                };
+                let cost = TRAVERSAL_COST
+                    + INTERSECT_COST
+                        * (1.0 - bonus)
+                        * (below_count as f64 * below.surface_area()
+                            + above_count as f64 * above.surface_area())
+                        / node_area;
+                if best.is_none_or(|(_, _, c)| cost < c) {
+                    best = Some((axis, position, cost));
+                }
+            }
+            if !is_end {
+                below_count += 1;
+            }
+        }
+    }
+
+    best
+}
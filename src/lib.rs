@@ -1,144 +1,1412 @@
 extern crate image;
 
+pub mod aabb;
+pub mod accelerator;
+pub mod bvh;
+pub mod camera;
+pub mod csg;
+pub mod film;
+pub mod group;
+pub mod heightfield;
+pub mod implicit;
+pub mod kdtree;
+pub mod matrix4;
+pub mod mesh;
+pub mod packet;
 pub mod point;
+pub mod polynomial;
 pub mod rendering;
+pub mod sampling;
 pub mod scene;
+pub mod sdf;
+pub mod transform;
 pub mod vector3;
 
-use image::*;
-use point::*;
-use rendering::*;
-use scene::{
-    Color, ColorType, DirectLight, Light, Material, Plane, Scene, Sphere, SphericalLight,
-    SurfaceType,
-};
-use std::time::SystemTime;
-use vector3::*;
-
-#[test]
-fn test_can_render_scene() {
-    let scene = Scene {
-        width: 800,
-        height: 600,
-        fov: 90.0,
-        bg_color: Color {
-            red: 0.01,
-            green: 0.02,
-            blue: 0.05,
-        },
-        lights: vec![
-            Light::Direct(DirectLight {
-                color: Color {
+#[cfg(test)]
+mod tests {
+    use crate::aabb::Aabb;
+    use crate::camera::{Camera, Equirectangular, Fisheye, Orthographic, Perspective, Projection};
+    use crate::csg::{Csg, CsgOperation};
+    use crate::film::Film;
+    use crate::group::Group;
+    use crate::heightfield::Heightfield;
+    use crate::implicit::{ImplicitSurface, Metaball};
+    use crate::matrix4::Matrix4;
+    use crate::mesh::{Face, Mesh};
+    use crate::packet::{self, RayPacket, PACKET_SIZE};
+    use crate::point::*;
+    use crate::polynomial::solve_quartic;
+    use crate::rendering::*;
+    use crate::sampling::{AdaptiveSampling, Filter, Rng, SamplePattern, SampleStats, Sampling};
+    use crate::scene::{
+        AxisAlignedBox, Color, ColorType, Cone, Cylinder, DirectLight, Disk, Light, Material,
+        Plane, Quad, Scene, Sphere, SphericalLight, SurfaceType, Torus, Triangle,
+    };
+    use crate::sdf::{self, SdfObject};
+    use crate::transform::Transformed;
+    use crate::vector3::*;
+    use image::*;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_can_render_scene() {
+        let mut scene = Scene {
+            width: 800,
+            height: 600,
+            camera: Camera::new(
+                Point::zero(),
+                Point::new(0.0, 0.0, -1.0),
+                Vector3::new(0.0, 1.0, 0.0),
+                Box::new(Perspective { fov: 90.0 }),
+            ),
+            sampling: Sampling {
+                adaptive: Some(AdaptiveSampling {
+                    max_samples: 16,
+                    threshold: 0.02,
+                }),
+                ..Sampling::new(4, SamplePattern::Stratified, Filter::Mitchell)
+            },
+            bg_color: Color {
+                red: 0.01,
+                green: 0.02,
+                blue: 0.05,
+            },
+            lights: vec![
+                Light::Direct(DirectLight {
+                    color: Color {
+                        red: 1.0,
+                        green: 1.0,
+                        blue: 1.0,
+                    },
+                    intensity: 8.0,
+                    direction: Vector3 {
+                        x: 1.0,
+                        y: -3.5,
+                        z: -4.0,
+                    },
+                }),
+                Light::Spherical(SphericalLight {
+                    color: Color {
+                        red: 1.0,
+                        green: 1.0,
+                        blue: 1.0,
+                    },
+                    intensity: 2000.0,
+                    position: Point {
+                        x: 2.0,
+                        y: -1.0,
+                        z: -4.5,
+                    },
+                }),
+            ],
+            objects: vec![
+                Box::new(Sphere::new(
+                    Point {
+                        x: -0.5,
+                        y: 0.5,
+                        z: -8.5,
+                    },
+                    1.5,
+                    Material {
+                        color: ColorType::Color(Color {
+                            red: 0.0,
+                            green: 1.0,
+                            blue: 0.0,
+                        }),
+                        surface_type: SurfaceType {
+                            diffuse_albedo: 0.0,
+                            reflect_ratio: 0.7,
+                            refractive_index: 0.0,
+                        },
+                    },
+                )),
+                Box::new(Sphere::new(
+                    Point {
+                        x: -3.6,
+                        y: 1.5,
+                        z: -7.0,
+                    },
+                    2.0,
+                    Material {
+                        color: ColorType::Texture(image::open("chessboard.png").unwrap()),
+                        surface_type: SurfaceType {
+                            diffuse_albedo: 0.3,
+                            reflect_ratio: 0.0,
+                            refractive_index: 0.0,
+                        },
+                    },
+                )),
+                Box::new(Sphere::new(
+                    Point {
+                        x: 2.0,
+                        y: 1.7,
+                        z: -5.0,
+                    },
+                    2.0,
+                    Material {
+                        color: ColorType::Color(Color {
+                            red: 1.0,
+                            green: 0.0,
+                            blue: 0.0,
+                        }),
+                        surface_type: SurfaceType {
+                            diffuse_albedo: 0.0,
+                            reflect_ratio: 0.0,
+                            refractive_index: 1.5,
+                        },
+                    },
+                )),
+                Box::new(Plane {
+                    normal: Vector3 {
+                        x: 0.0,
+                        y: -1.0,
+                        z: 0.0,
+                    },
+                    center: Point {
+                        x: 0.0,
+                        y: -3.0,
+                        z: 0.0,
+                    },
+                    material: Material {
+                        color: ColorType::Texture(image::open("chessboard.png").unwrap()),
+                        surface_type: SurfaceType {
+                            diffuse_albedo: 0.18,
+                            reflect_ratio: 0.5,
+                            refractive_index: 0.0,
+                        },
+                    },
+                }),
+            ],
+            accelerator: None,
+        };
+        scene.build_bvh();
+
+        let sys_time = SystemTime::now();
+        let img: DynamicImage = render_in_threads(scene, 8);
+        println!(
+            "render_in_threads execution time {} ms",
+            sys_time.elapsed().unwrap().as_millis()
+        );
+        img.save("test-multithreaded.png").unwrap();
+    }
+
+    #[test]
+    fn test_camera_looking_along_up() {
+        let camera = Camera::new(
+            Point::new(0.0, 5.0, 0.0),
+            Point::zero(),
+            Vector3::new(0.0, 1.0, 0.0),
+            Box::new(Perspective { fov: 90.0 }),
+        );
+        let basis = camera.basis();
+        let (right, up, back) = basis;
+        assert!((back.y - 1.0).abs() < 1e-12);
+        assert!((right.length() - 1.0).abs() < 1e-12 && (up.length() - 1.0).abs() < 1e-12);
+        assert!(right.dot(&back).abs() < 1e-12 && up.dot(&back).abs() < 1e-12);
+
+        let ray = camera.ray(&basis, 0.5, 0.5, 1.0, &mut Rng::new(1)).unwrap();
+        assert!((ray.direction.y + 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_fov_spans_shorter_side() {
+        for &(width, height) in &[(400, 200), (200, 400), (300, 300)] {
+            let scene = Scene {
+                width,
+                height,
+                camera: Camera::new(
+                    Point::zero(),
+                    Point::new(0.0, 0.0, -1.0),
+                    Vector3::new(0.0, 1.0, 0.0),
+                    Box::new(Perspective { fov: 90.0 }),
+                ),
+                sampling: Sampling::default(),
+                bg_color: Color {
+                    red: 0.0,
+                    green: 0.0,
+                    blue: 0.0,
+                },
+                lights: vec![],
+                objects: vec![],
+                accelerator: None,
+            };
+            // the shorter side spans the whole fov, so its edge ray is close to 45 degrees
+            let (x, y) = if width <= height {
+                (0.5, f64::from(height) / 2.0)
+            } else {
+                (f64::from(width) / 2.0, 0.5)
+            };
+            let ray =
+                Ray::create_prime(x, y, &scene, &scene.camera.basis(), &mut Rng::new(0)).unwrap();
+            let edge = ray.direction.x.abs().max(ray.direction.y.abs()) / -ray.direction.z;
+            assert!((edge - 1.0).abs() < 0.02, "{}x{}: {}", width, height, edge);
+        }
+    }
+
+    #[test]
+    fn test_thin_lens_focuses_on_focal_plane() {
+        let mut camera = Camera::new(
+            Point::zero(),
+            Point::new(0.0, 0.0, -1.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            Box::new(Perspective { fov: 60.0 }),
+        );
+        camera.aperture = 0.5;
+        camera.focal_distance = 5.0;
+
+        // rays from different lens points through the same image point
+        let basis = camera.basis();
+        let mut rng = Rng::new(3);
+        let rays: Vec<Ray> = (0..8)
+            .map(|_| camera.ray(&basis, 0.3, 0.6, 1.5, &mut rng).unwrap())
+            .collect();
+        let focus =
+            |ray: &Ray| ray.origin + ray.direction * ((-5.0 - ray.origin.z) / ray.direction.z);
+        let expected = focus(&rays[0]);
+        for ray in &rays[1..] {
+            assert!((ray.origin - rays[0].origin).length() > 1e-6);
+            assert!((focus(ray) - expected).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_orthographic_rays_are_parallel() {
+        let camera = Camera::new(
+            Point::new(0.0, 0.0, 10.0),
+            Point::zero(),
+            Vector3::new(0.0, 1.0, 0.0),
+            Box::new(Orthographic { size: 4.0 }),
+        );
+        let basis = camera.basis();
+        let mut rng = Rng::new(1);
+        // 2:1 image, the shorter side spans 4 world units
+        let left = camera.ray(&basis, 0.0, 0.5, 2.0, &mut rng).unwrap();
+        let right = camera.ray(&basis, 1.0, 0.5, 2.0, &mut rng).unwrap();
+        let top = camera.ray(&basis, 0.5, 0.0, 2.0, &mut rng).unwrap();
+        let corner = camera.ray(&basis, 0.2, 0.9, 2.0, &mut rng).unwrap();
+
+        assert!((left.origin - Point::new(-4.0, 0.0, 10.0)).length() < 1e-12);
+        assert!((right.origin - Point::new(4.0, 0.0, 10.0)).length() < 1e-12);
+        assert!((top.origin - Point::new(0.0, 2.0, 10.0)).length() < 1e-12);
+        for ray in &[left, right, top, corner] {
+            assert!((ray.direction - Vector3::new(0.0, 0.0, -1.0)).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_panoramic_projections() {
+        let close = |a: Vector3, b: Vector3| (a - b).length() < 1e-12;
+
+        let fisheye = Fisheye { fov: 180.0 };
+        assert!(close(
+            fisheye.project(0.5, 0.5, 1.0).unwrap().1,
+            Vector3::new(0.0, 0.0, -1.0)
+        ));
+        // the image circle touches the image sides at 90° from the view direction
+        assert!(close(
+            fisheye.project(1.0, 0.5, 1.0).unwrap().1,
+            Vector3::new(1.0, 0.0, 0.0)
+        ));
+        assert!(close(
+            fisheye.project(0.5, 0.0, 1.0).unwrap().1,
+            Vector3::new(0.0, 1.0, 0.0)
+        ));
+        assert!(fisheye.project(0.0, 0.0, 1.0).is_none());
+        assert!(fisheye.project(0.95, 0.95, 1.0).is_none());
+        // outside of the circle on the longer side of a wide image
+        assert!(fisheye.project(0.05, 0.5, 2.0).is_none());
+
+        let panorama = Equirectangular;
+        let direction = |u, v| panorama.project(u, v, 2.0).unwrap().1;
+        assert!(close(direction(0.5, 0.5), Vector3::new(0.0, 0.0, -1.0)));
+        assert!(close(direction(0.75, 0.5), Vector3::new(1.0, 0.0, 0.0)));
+        assert!(close(direction(0.25, 0.5), Vector3::new(-1.0, 0.0, 0.0)));
+        assert!(close(direction(0.0, 0.5), Vector3::new(0.0, 0.0, 1.0)));
+        // the top and bottom edges are the poles
+        assert!(close(direction(0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)));
+        assert!(close(direction(1.0, 1.0), Vector3::new(0.0, -1.0, 0.0)));
+    }
+
+    #[test]
+    fn test_filter_weights() {
+        assert_eq!(Filter::Box.weight(0.3, -0.4), 1.0);
+        assert_eq!(Filter::Box.weight(0.6, 0.0), 0.0);
+        assert!((Filter::Tent.weight(0.5, 0.0) - 0.5).abs() < 1e-12);
+        assert!((Filter::Tent.weight(0.5, 0.5) - 0.25).abs() < 1e-12);
+        assert_eq!(Filter::Tent.weight(1.0, 0.0), 0.0);
+
+        // the gaussian falls off smoothly to zero at the radius
+        let gaussian = |d| Filter::Gaussian.weight(d, 0.0);
+        assert!(gaussian(0.0) > gaussian(0.5) && gaussian(0.5) > gaussian(1.0));
+        assert!(gaussian(1.4) > 0.0 && gaussian(1.4) < 0.01);
+        assert_eq!(gaussian(1.6), 0.0);
+
+        // Mitchell peaks at 8/9 and has negative lobes between 1 and 2
+        let mitchell = |d| Filter::Mitchell.weight(d, 0.0) / Filter::Mitchell.weight(0.0, 0.0);
+        assert!((Filter::Mitchell.weight(0.0, 0.0) - 64.0 / 81.0).abs() < 1e-12);
+        assert!(mitchell(1.5) < 0.0);
+        assert!(mitchell(2.0).abs() < 1e-12);
+        assert_eq!(mitchell(2.1), 0.0);
+    }
+
+    #[test]
+    fn test_film_normalizes_weights() {
+        let gray = |v| Color {
+            red: v,
+            green: v,
+            blue: v,
+        };
+        let close =
+            |a: Rgba<u8>, b: Rgba<u8>| (0..3).all(|c| (a[c] as i32 - b[c] as i32).abs() <= 1);
+
+        // weighted mean of the samples regardless of the weights scale
+        let mut film = Film::new(0, 0, 2, 1);
+        film.add_sample(0.5, 0.5, gray(0.2), &Filter::Tent);
+        film.add_sample(1.0, 0.5, gray(0.8), &Filter::Tent);
+        let image = film.to_image();
+        assert!(close(image.get_pixel(0, 0), gray(0.4).to_rgba()));
+        // the second pixel only sees the second sample
+        assert!(close(image.get_pixel(1, 0), gray(0.8).to_rgba()));
+
+        // negative lobes of neighbours outweigh or nearly cancel the own sample
+        // at the pixel corner, the pixel falls back to the mean of its own samples
+        for neighbours in [12, 9] {
+            let mut film = Film::new(0, 0, 1, 1);
+            film.add_sample(0.99, 0.99, gray(0.5), &Filter::Mitchell);
+            for _ in 0..neighbours {
+                film.add_sample(2.0, 0.5, gray(1.0), &Filter::Mitchell);
+            }
+            assert!(close(film.to_image().get_pixel(0, 0), gray(0.5).to_rgba()));
+        }
+
+        // small weights that do not cancel out are kept whatever their scale
+        let mut film = Film::new(0, 0, 1, 1);
+        film.add_sample(1.495, 0.5, gray(0.6), &Filter::Tent);
+        assert!(close(film.to_image().get_pixel(0, 0), gray(0.6).to_rgba()));
+    }
+
+    #[test]
+    fn test_adaptive_sampling() {
+        let mut stats = SampleStats::new();
+        for value in &[1.0, 2.0, 3.0, 4.0] {
+            stats.add(*value);
+        }
+        // sample variance 5/3 over 4 samples
+        assert!((stats.standard_error() - (5.0_f64 / 12.0).sqrt()).abs() < 1e-12);
+
+        let mut sampling = Sampling::new(4, SamplePattern::Stratified, Filter::Box);
+        sampling.adaptive = Some(AdaptiveSampling {
+            max_samples: 10,
+            threshold: 0.01,
+        });
+        assert_eq!(sampling.extra_samples(&stats), 4);
+        for value in &[1.0, 2.0, 3.0, 4.0] {
+            stats.add(*value);
+        }
+        // only 2 more fit under the max
+        assert_eq!(sampling.extra_samples(&stats), 2);
+        stats.add(1.0);
+        stats.add(4.0);
+        assert_eq!(sampling.extra_samples(&stats), 0);
+
+        let mut flat = SampleStats::new();
+        for _ in 0..4 {
+            flat.add(0.5);
+        }
+        assert_eq!(sampling.extra_samples(&flat), 0);
+
+        // partial extra batches spread over the whole pixel
+        let mut rng = Rng::new(5);
+        let positions: Vec<(f64, f64)> = (0..200)
+            .flat_map(|_| sampling.extra_positions(2, &mut rng))
+            .collect();
+        for half in &[|p: &(f64, f64)| p.0 > 0.5, |p: &(f64, f64)| p.1 > 0.5] {
+            let count = positions.iter().filter(|p| half(p)).count();
+            assert!(count > 150 && count < 250);
+        }
+
+        // one sample per pixel can not estimate the variance, batches take 2
+        sampling.samples_per_pixel = 1;
+        sampling.pattern = SamplePattern::Jittered;
+        assert_eq!(sampling.positions(&mut rng).len(), 2);
+        let mut single = SampleStats::new();
+        single.add(0.0);
+        single.add(1.0);
+        assert_eq!(sampling.extra_samples(&single), 2);
+    }
+
+    #[test]
+    fn test_triangle_intersection() {
+        let triangle = Triangle::new(
+            Point::new(-1.0, -1.0, -5.0),
+            Point::new(1.0, -1.0, -5.0),
+            Point::new(-1.0, 1.0, -5.0),
+            Material {
+                color: ColorType::Color(Color {
                     red: 1.0,
                     green: 1.0,
                     blue: 1.0,
+                }),
+                surface_type: SurfaceType {
+                    diffuse_albedo: 0.18,
+                    reflect_ratio: 0.0,
+                    refractive_index: 0.0,
                 },
-                intensity: 8.0,
-                direction: Vector3 {
-                    x: 1.0,
-                    y: -3.5,
-                    z: -4.0,
-                },
-            }),
-            Light::Spherical(SphericalLight {
-                color: Color {
+            },
+        );
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(-0.1, -0.1, -1.0).normalize(),
+        };
+        let distance = triangle.intersect(&ray).unwrap();
+        let hit_point = ray.origin + ray.direction * distance;
+        assert!((hit_point.z + 5.0).abs() < 1e-9);
+        assert!(triangle.surface_normal(&hit_point).z > 0.999);
+        let coords = triangle.texture_coords(&hit_point);
+        assert!((coords.x - 0.25).abs() < 1e-6 && (coords.y - 0.25).abs() < 1e-6);
+
+        let miss = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.5, 0.5, -1.0).normalize(),
+        };
+        assert!(triangle.intersect(&miss).is_none());
+
+        let behind = Ray {
+            origin: Point::new(0.0, 0.0, -10.0),
+            direction: Vector3::new(-0.1, -0.1, 1.0).normalize(),
+        };
+        assert!(triangle.intersection(&behind).unwrap().normal.z < -0.999);
+    }
+
+    #[test]
+    fn test_can_load_obj_mesh() {
+        let dir = std::env::temp_dir().join("raytracer-test-obj");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("quad.mtl"),
+            "newmtl red\nKd 1.0 0.0 0.0\nillum 2\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("quad.obj"),
+            "mtllib quad.mtl\n\
+             v -1 -1 -5\nv 1 -1 -5\nv 1 1 -5\nv -1 1 -5\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             vn 0 0 1\n\
+             usemtl red\n\
+             f 1/1/1 2/2/1 3/3/1 4/4/1\n",
+        )
+        .unwrap();
+
+        let mesh = Mesh::load_obj(
+            dir.join("quad.obj"),
+            Material {
+                color: ColorType::Color(Color {
                     red: 1.0,
                     green: 1.0,
                     blue: 1.0,
+                }),
+                surface_type: SurfaceType {
+                    diffuse_albedo: 0.18,
+                    reflect_ratio: 0.0,
+                    refractive_index: 0.0,
                 },
-                intensity: 2000.0,
-                position: Point {
-                    x: 2.0,
-                    y: -1.0,
-                    z: -4.5,
-                },
+            },
+        )
+        .unwrap();
+        assert_eq!(mesh.faces.len(), 2);
+        assert_eq!(mesh.materials.len(), 2);
+
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.1, 0.1, -1.0).normalize(),
+        };
+        let hit = mesh.intersection(&ray).unwrap();
+        assert!((hit.distance - ray.direction.z.recip().abs() * 5.0).abs() < 1e-9);
+        assert!(hit.normal.z > 0.999);
+        let color = hit.material.color(&hit.texture_coords);
+        assert!(color.red > 0.99 && color.green < 0.01);
+        // interpolated normals turn towards rays from behind as well
+        let behind = Ray {
+            origin: Point::new(0.0, 0.0, -10.0),
+            direction: Vector3::new(0.1, 0.1, 1.0).normalize(),
+        };
+        assert!(mesh.intersection(&behind).unwrap().normal.z < -0.999);
+    }
+
+    fn white_material() -> Material {
+        Material {
+            color: ColorType::Color(Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
             }),
-        ],
-        objects: vec![
+            surface_type: SurfaceType {
+                diffuse_albedo: 0.18,
+                reflect_ratio: 0.0,
+                refractive_index: 0.0,
+            },
+        }
+    }
+
+    fn empty_scene() -> Scene {
+        Scene {
+            width: 64,
+            height: 64,
+            camera: Camera::new(
+                Point::zero(),
+                Point::new(0.0, 0.0, -1.0),
+                Vector3::new(0.0, 1.0, 0.0),
+                Box::new(Perspective { fov: 90.0 }),
+            ),
+            sampling: Sampling::default(),
+            bg_color: Color {
+                red: 0.0,
+                green: 0.0,
+                blue: 0.0,
+            },
+            lights: vec![],
+            objects: vec![],
+            accelerator: None,
+        }
+    }
+
+    #[test]
+    fn test_accelerators_match_linear_trace() {
+        let mut rng = Rng::new(7);
+        let mut scene = empty_scene();
+        for _ in 0..300 {
+            let center = Point::new(
+                rng.next_f64() * 20.0 - 10.0,
+                rng.next_f64() * 20.0 - 10.0,
+                rng.next_f64() * -20.0 - 2.0,
+            );
+            scene.objects.push(Box::new(Sphere::new(
+                center,
+                rng.next_f64() * 0.5 + 0.1,
+                white_material(),
+            )));
+        }
+        scene.objects.push(Box::new(Plane {
+            normal: Vector3::new(0.0, -1.0, 0.0),
+            center: Point::new(0.0, -8.0, 0.0),
+            material: white_material(),
+        }));
+
+        let rays: Vec<Ray> = (0..2000)
+            .map(|_| Ray {
+                origin: Point::zero(),
+                direction: Vector3::new(
+                    rng.next_f64() * 2.0 - 1.0,
+                    rng.next_f64() * 2.0 - 1.0,
+                    -1.0,
+                )
+                .normalize(),
+            })
+            .collect();
+        let linear: Vec<Option<f64>> = rays
+            .iter()
+            .map(|ray| scene.trace(ray).map(|hit| hit.distance))
+            .collect();
+        // any-hit queries agree with the closest hit and so do packets,
+        // the last one is only partially filled
+        let max_distance = 15.0;
+        let check = |scene: &Scene| {
+            for (ray, expected) in rays.iter().zip(&linear) {
+                assert_eq!(scene.trace(ray).map(|hit| hit.distance), *expected);
+                assert_eq!(
+                    scene.occluded(ray, max_distance),
+                    expected.is_some_and(|d| d < max_distance)
+                );
+            }
+            for (chunk, expected) in rays[1..]
+                .chunks(PACKET_SIZE)
+                .zip(linear[1..].chunks(PACKET_SIZE))
+            {
+                let hits = scene.trace_packet(&RayPacket::new(chunk));
+                for (hit, expected) in hits.iter().zip(expected) {
+                    assert_eq!(hit.as_ref().map(|hit| hit.distance), *expected);
+                }
+            }
+        };
+        check(&scene);
+        for build in [Scene::build_bvh as fn(&mut Scene), Scene::build_kd_tree] {
+            build(&mut scene);
+            check(&scene);
+        }
+    }
+
+    #[test]
+    fn test_accelerators_handle_deep_trees() {
+        // exponentially spaced spheres split off one at a time,
+        // the hierarchy would be deeper than its traversal stacks
+        let mut scene = empty_scene();
+        let centers: Vec<Point> = (0..150)
+            .map(|i| Point::new(10f64.powi(i), 0.0, -5.0))
+            .collect();
+        for center in &centers {
+            scene.objects.push(Box::new(Sphere::new(
+                *center,
+                center.x * 0.1,
+                white_material(),
+            )));
+        }
+        let rays: Vec<Ray> = centers
+            .iter()
+            .map(|center| Ray {
+                origin: Point::new(0.0, 0.0, 5.0),
+                direction: (*center - Point::new(0.0, 0.0, 5.0)).normalize(),
+            })
+            .collect();
+        let linear: Vec<Option<f64>> = rays
+            .iter()
+            .map(|ray| scene.trace(ray).map(|hit| hit.distance))
+            .collect();
+        for build in [Scene::build_bvh as fn(&mut Scene), Scene::build_kd_tree] {
+            build(&mut scene);
+            for (ray, expected) in rays.iter().zip(&linear) {
+                assert_eq!(scene.trace(ray).map(|hit| hit.distance), *expected);
+                assert_eq!(scene.occluded(ray, f64::INFINITY), expected.is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_axis_aligned_box_intersection() {
+        let cube = AxisAlignedBox::new(
+            Point::new(-1.0, -1.0, -6.0),
+            Point::new(1.0, 1.0, -4.0),
+            white_material(),
+        );
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = cube.intersection(&ray).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-9);
+        assert!(hit.normal.z > 0.999);
+        assert!((hit.texture_coords.x - 0.5).abs() < 1e-6);
+
+        let inside = Ray {
+            origin: Point::new(0.0, 0.0, -5.0),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+        };
+        let hit = cube.intersection(&inside).unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-9);
+        assert!(hit.normal.x > 0.999);
+
+        let miss = Ray {
+            origin: Point::new(0.0, 2.0, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        assert!(cube.intersect(&miss).is_none());
+    }
+
+    #[test]
+    fn test_disk_and_quad_are_bounded() {
+        let disk = Disk::new(
+            Point::new(0.0, 0.0, -5.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            1.0,
+            white_material(),
+        );
+        let quad = Quad::new(
+            Point::new(-1.0, -1.0, -5.0),
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            white_material(),
+        );
+        let ray_to = |x: f64, y: f64| Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(x, y, -5.0).normalize(),
+        };
+
+        assert!(disk.intersect(&ray_to(0.7, 0.7)).is_some());
+        assert!(disk.intersect(&ray_to(0.8, 0.8)).is_none());
+        let hit = quad.intersection(&ray_to(0.5, -0.5)).unwrap();
+        assert!(hit.normal.z > 0.999);
+        assert!((hit.texture_coords.x - 0.75).abs() < 1e-6);
+        assert!((hit.texture_coords.y - 0.5).abs() < 1e-6);
+        assert!(quad.intersect(&ray_to(0.5, 0.5)).is_none());
+    }
+
+    #[test]
+    fn test_quad_is_lit_from_behind() {
+        // the quad faces away from the camera and the light
+        let mut scene = empty_scene();
+        scene.lights.push(Light::Direct(DirectLight {
+            color: Color {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+            },
+            intensity: 8.0,
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        }));
+        scene.objects.push(Box::new(Quad::new(
+            Point::new(-1.0, -1.0, -3.0),
+            Vector3::new(0.0, 2.0, 0.0),
+            Vector3::new(2.0, 0.0, 0.0),
+            white_material(),
+        )));
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        assert!(scene.trace(&ray).unwrap().normal.z > 0.999);
+
+        let image = render(&scene, 0, scene.width);
+        let pixel = image.get_pixel(32, 32);
+        assert!(pixel[0] > 0 && pixel[1] > 0 && pixel[2] > 0);
+    }
+
+    #[test]
+    fn test_cylinder_and_cone_intersection() {
+        let cylinder = Cylinder::new(
+            Point::new(0.0, -1.0, -5.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            1.0,
+            2.0,
+            true,
+            white_material(),
+        );
+        let side = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = cylinder.intersection(&side).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-9);
+        assert!(hit.normal.z > 0.999);
+
+        let top = Ray {
+            origin: Point::new(0.2, 5.0, -5.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        let hit = cylinder.intersection(&top).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-9);
+        assert!(hit.normal.y > 0.999);
+
+        let cone = Cone::new(
+            Point::new(0.0, -1.0, -5.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            1.0,
+            2.0,
+            false,
+            white_material(),
+        );
+        // halfway up the cone radius is 0.5
+        let hit = cone.intersection(&side).unwrap();
+        assert!((hit.distance - 4.5).abs() < 1e-9);
+        assert!(hit.normal.z > 0.0 && hit.normal.y > 0.0);
+        // from above the ray meets the slope where the radius is 0.2
+        assert!((cone.intersect(&top).unwrap() - 4.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_torus_intersection() {
+        // (t - 1)(t - 2)(t - 3)(t - 4)
+        let roots = solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0);
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip(&[1.0, 2.0, 3.0, 4.0]) {
+            assert!((root - expected).abs() < 1e-9);
+        }
+
+        let torus = Torus::new(
+            Point::new(0.0, 0.0, -10.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            2.0,
+            0.5,
+            white_material(),
+        );
+        // in the plane of the ring the ray hits the outer side of the tube first
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = torus.intersection(&ray).unwrap();
+        assert!((hit.distance - 7.5).abs() < 1e-9);
+        assert!(hit.normal.z > 0.999);
+
+        let through_hole = Ray {
+            origin: Point::new(0.0, 5.0, -10.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        assert!(torus.intersect(&through_hole).is_none());
+
+        let top = Ray {
+            origin: Point::new(2.0, 5.0, -10.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        let hit = torus.intersection(&top).unwrap();
+        assert!((hit.distance - 4.5).abs() < 1e-9);
+        assert!(hit.normal.y > 0.999);
+    }
+
+    #[test]
+    fn test_transformed_sphere_is_ellipsoid() {
+        let matrix = Matrix4::translation(Vector3::new(0.0, 0.0, -10.0))
+            * Matrix4::rotation(Vector3::new(0.0, 0.0, 1.0), 90.0)
+            * Matrix4::scaling(1.0, 2.0, 1.0);
+        let ellipsoid = Transformed::new(Sphere::new(Point::zero(), 1.0, white_material()), matrix);
+        // the long axis is turned from y to x
+        let side = Ray {
+            origin: Point::new(10.0, 0.0, -10.0),
+            direction: Vector3::new(-1.0, 0.0, 0.0),
+        };
+        let hit = ellipsoid.intersection(&side).unwrap();
+        assert!((hit.distance - 8.0).abs() < 1e-9);
+        assert!(hit.normal.x > 0.999);
+        let top = Ray {
+            origin: Point::new(0.0, 5.0, -10.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        assert!((ellipsoid.intersect(&top).unwrap() - 4.0).abs() < 1e-9);
+        let bounds = ellipsoid.bounds();
+        assert!((bounds.max.x - 2.0).abs() < 1e-9 && (bounds.max.y - 1.0).abs() < 1e-9);
+
+        // instances share one object
+        let shared = Arc::new(Sphere::new(Point::zero(), 1.0, white_material()));
+        let left = Transformed::new(
+            shared.clone(),
+            Matrix4::translation(Vector3::new(-3.0, 0.0, 0.0)),
+        );
+        let right = Transformed::new(shared, Matrix4::translation(Vector3::new(3.0, 0.0, 0.0)));
+        let ray = Ray {
+            origin: Point::new(3.0, 0.0, 5.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        assert!(left.intersect(&ray).is_none());
+        assert!((right.intersect(&ray).unwrap() - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_csg_lens_and_bowl() {
+        let lens = Csg::new(
+            CsgOperation::Intersection,
             Box::new(Sphere::new(
-                Point {
-                    x: -0.5,
-                    y: 0.5,
-                    z: -8.5,
-                },
-                1.5,
-                Material {
-                    color: ColorType::Color(Color {
-                        red: 0.0,
-                        green: 1.0,
-                        blue: 0.0,
-                    }),
-                    surface_type: SurfaceType {
-                        diffuse_albedo: 0.0,
-                        reflect_ratio: 0.7,
-                        refractive_index: 0.0,
-                    },
-                },
+                Point::new(-0.5, 0.0, 0.0),
+                1.0,
+                white_material(),
             )),
             Box::new(Sphere::new(
-                Point {
-                    x: -3.6,
-                    y: 1.5,
-                    z: -7.0,
-                },
-                2.0,
-                Material {
-                    color: ColorType::Texture(image::open("chessboard.png").unwrap()),
-                    surface_type: SurfaceType {
-                        diffuse_albedo: 0.3,
-                        reflect_ratio: 0.0,
-                        refractive_index: 0.0,
-                    },
-                },
+                Point::new(0.5, 0.0, 0.0),
+                1.0,
+                white_material(),
+            )),
+        );
+        let ray = Ray {
+            origin: Point::new(5.0, 0.0, 0.0),
+            direction: Vector3::new(-1.0, 0.0, 0.0),
+        };
+        let hit = lens.intersection(&ray).unwrap();
+        assert!((hit.distance - 4.5).abs() < 1e-9);
+        assert!(hit.normal.x > 0.999);
+
+        let bowl = Csg::new(
+            CsgOperation::Difference,
+            Box::new(AxisAlignedBox::new(
+                Point::new(-1.0, -1.0, -1.0),
+                Point::new(1.0, 1.0, 1.0),
+                white_material(),
             )),
             Box::new(Sphere::new(
-                Point {
-                    x: 2.0,
-                    y: 1.7,
-                    z: -5.0,
-                },
-                2.0,
-                Material {
-                    color: ColorType::Color(Color {
-                        red: 1.0,
-                        green: 0.0,
-                        blue: 0.0,
-                    }),
-                    surface_type: SurfaceType {
-                        diffuse_albedo: 0.0,
-                        reflect_ratio: 0.0,
-                        refractive_index: 1.5,
-                    },
-                },
+                Point::new(0.0, 1.0, 0.0),
+                0.8,
+                white_material(),
+            )),
+        );
+        // from above the ray passes the hollow and hits its bottom
+        let top = Ray {
+            origin: Point::new(0.0, 5.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        let hit = bowl.intersection(&top).unwrap();
+        assert!((hit.distance - 4.8).abs() < 1e-9);
+        assert!(hit.normal.y > 0.999);
+        let side = Ray {
+            origin: Point::new(5.0, 0.9, 0.0),
+            direction: Vector3::new(-1.0, 0.0, 0.0),
+        };
+        assert!((bowl.intersect(&side).unwrap() - 4.0).abs() < 1e-9);
+        // from inside the hollow the ray leaves through the wall
+        let inside = Ray {
+            origin: Point::new(0.0, 0.9, 0.0),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+        };
+        let hit = bowl.intersection(&inside).unwrap();
+        assert!((hit.distance - 0.63_f64.sqrt()).abs() < 1e-9);
+        assert!(hit.normal.x < -0.9);
+    }
+
+    #[test]
+    fn test_csg_with_cylinder_and_cone() {
+        // box with a hole drilled through along y
+        let drilled = Csg::new(
+            CsgOperation::Difference,
+            Box::new(AxisAlignedBox::new(
+                Point::new(-1.0, -1.0, -1.0),
+                Point::new(1.0, 1.0, 1.0),
+                white_material(),
             )),
+            Box::new(Cylinder::new(
+                Point::new(0.0, -2.0, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+                0.5,
+                4.0,
+                true,
+                white_material(),
+            )),
+        );
+        let down = |x| Ray {
+            origin: Point::new(x, 5.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        assert!(drilled.intersect(&down(0.0)).is_none());
+        assert!((drilled.intersect(&down(0.8)).unwrap() - 4.0).abs() < 1e-9);
+        let from_hole = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(1.0, 0.0, 0.0),
+        };
+        let hit = drilled.intersection(&from_hole).unwrap();
+        assert!((hit.distance - 0.5).abs() < 1e-9);
+        assert!(hit.normal.x < -0.999);
+
+        // the span of a ray starting inside the cone begins behind it
+        let cone = Cone::new(
+            Point::new(0.0, -1.0, -5.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            1.0,
+            2.0,
+            true,
+            white_material(),
+        );
+        let inside = Ray {
+            origin: Point::new(0.0, 0.0, -5.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        let spans = cone.spans(&inside).unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!((spans[0].enter.distance + 1.0).abs() < 1e-9);
+        assert!((spans[0].exit.distance - 1.0).abs() < 1e-9);
+        assert!(spans[0].exit.normal.y < -0.999);
+    }
+
+    #[test]
+    #[should_panic(expected = "CSG operands must be solids")]
+    fn test_csg_rejects_surfaces_without_inside() {
+        Csg::new(
+            CsgOperation::Union,
+            Box::new(Sphere::new(Point::zero(), 1.0, white_material())),
             Box::new(Plane {
-                normal: Vector3 {
-                    x: 0.0,
-                    y: -1.0,
-                    z: 0.0,
-                },
-                center: Point {
-                    x: 0.0,
-                    y: -3.0,
-                    z: 0.0,
-                },
-                material: Material {
-                    color: ColorType::Texture(image::open("chessboard.png").unwrap()),
-                    surface_type: SurfaceType {
-                        diffuse_albedo: 0.18,
-                        reflect_ratio: 0.5,
-                        refractive_index: 0.0,
-                    },
-                },
+                normal: Vector3::new(0.0, -1.0, 0.0),
+                center: Point::zero(),
+                material: white_material(),
             }),
-        ],
-    };
+        );
+    }
+
+    #[test]
+    fn test_csg_literal_ignores_surfaces_without_inside() {
+        let csg = Csg {
+            operation: CsgOperation::Union,
+            left: Box::new(Sphere::new(
+                Point::new(0.0, 0.0, -5.0),
+                1.0,
+                white_material(),
+            )),
+            right: Box::new(Plane {
+                normal: Vector3::new(0.0, 0.0, 1.0),
+                center: Point::new(0.0, 0.0, -2.0),
+                material: white_material(),
+            }),
+        };
+        let ray = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        assert!((csg.intersect(&ray).unwrap() - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sdf_sphere_tracing() {
+        let bounds = Aabb::new(Point::new(-3.0, -2.0, -2.0), Point::new(3.0, 2.0, 2.0));
+        let ball = SdfObject::new(
+            sdf::sphere(Point::new(0.0, 0.0, -1.0), 1.0),
+            bounds,
+            white_material(),
+        );
+        let ray = Ray {
+            origin: Point::new(0.0, 0.0, 5.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = ball.intersection(&ray).unwrap();
+        assert!((hit.distance - 5.0).abs() < 1e-5);
+        assert!(hit.normal.z > 0.999);
+
+        // the gap between the spheres is filled only by the smooth blend
+        let pair = || {
+            (
+                sdf::sphere(Point::new(-1.2, 0.0, 0.0), 1.0),
+                sdf::sphere(Point::new(1.2, 0.0, 0.0), 1.0),
+            )
+        };
+        let down = Ray {
+            origin: Point::new(0.0, 5.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        let (a, b) = pair();
+        let union = SdfObject::new(sdf::union(a, b), bounds, white_material());
+        assert!(union.intersect(&down).is_none());
+        let (a, b) = pair();
+        let blend = SdfObject::new(sdf::smooth_union(a, b, 1.0), bounds, white_material());
+        assert!((blend.intersect(&down).unwrap() - 4.65).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_heightfield_from_image() {
+        // a single peak in the middle of a flat 3x3 grid
+        let image = DynamicImage::ImageLuma8(ImageBuffer::from_fn(3, 3, |x, y| Luma {
+            data: [if x == 1 && y == 1 { 255 } else { 0 }],
+        }));
+        let terrain = Heightfield::from_image(
+            &image,
+            Point::new(-1.0, 0.0, -1.0),
+            Vector3::new(2.0, 1.0, 2.0),
+            white_material(),
+        );
+        let peak = Ray {
+            origin: Point::new(0.0, 5.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        let hit = terrain.intersection(&peak).unwrap();
+        assert!((hit.distance - 4.0).abs() < 1e-9);
+        assert!(hit.normal.y > 0.999);
+
+        let slope = Ray {
+            origin: Point::new(-0.25, 5.0, -0.25),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        assert!((terrain.intersect(&slope).unwrap() - 4.5).abs() < 1e-9);
+        let side = Ray {
+            origin: Point::new(5.0, 0.5, 0.25),
+            direction: Vector3::new(-1.0, 0.0, 0.0),
+        };
+        assert!((terrain.intersect(&side).unwrap() - 4.75).abs() < 1e-9);
+        let above = Ray {
+            origin: Point::new(5.0, 1.5, 0.0),
+            direction: Vector3::new(-1.0, 0.0, 0.0),
+        };
+        assert!(terrain.intersect(&above).is_none());
+    }
+
+    #[test]
+    fn test_metaballs_intersection() {
+        // the field of a single ball reaches 0.125 at the distance sqrt(2)
+        let ball = ImplicitSurface::metaballs(
+            vec![Metaball::new(Point::zero(), 2.0, 1.0)],
+            0.125,
+            white_material(),
+        );
+        let ray = Ray {
+            origin: Point::new(0.0, 0.0, 5.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = ball.intersection(&ray).unwrap();
+        assert!((hit.distance - (5.0 - 2.0_f64.sqrt())).abs() < 1e-9);
+        assert!(hit.normal.z > 0.999);
+
+        // between two balls the fields add up and fill the gap
+        let blob = ImplicitSurface::metaballs(
+            vec![
+                Metaball::new(Point::new(-1.5, 0.0, 0.0), 2.0, 1.0),
+                Metaball::new(Point::new(1.5, 0.0, 0.0), 2.0, 1.0),
+            ],
+            0.125,
+            white_material(),
+        );
+        let down = Ray {
+            origin: Point::new(0.0, 5.0, 0.0),
+            direction: Vector3::new(0.0, -1.0, 0.0),
+        };
+        let distance = blob.intersect(&down).unwrap();
+        let hit_point = down.origin + down.direction * distance;
+        assert!(distance < 5.0);
+        assert!(((blob.field)(&hit_point) - 0.125).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_nested_groups() {
+        let mut pair = Group::new();
+        pair.add(Box::new(Sphere::new(
+            Point::new(-2.0, 0.0, 0.0),
+            1.0,
+            white_material(),
+        )));
+        pair.add(Box::new(Sphere::new(
+            Point::new(2.0, 0.0, 0.0),
+            1.0,
+            white_material(),
+        )));
+        pair.build_bvh();
+
+        // the pair is moved away and scaled twice
+        let mut scene_root = Group::with_transform(
+            Matrix4::translation(Vector3::new(0.0, 0.0, -10.0)) * Matrix4::scaling(2.0, 2.0, 2.0),
+        );
+        scene_root.add(Box::new(pair));
+        let bounds = scene_root.bounds();
+        assert!((bounds.min.x + 6.0).abs() < 1e-9 && (bounds.max.z + 8.0).abs() < 1e-9);
+
+        let ray = Ray {
+            origin: Point::new(4.0, 0.0, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let hit = scene_root.intersection(&ray).unwrap();
+        assert!((hit.distance - 8.0).abs() < 1e-9);
+        assert!(hit.normal.z > 0.999);
+        let between = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        assert!(scene_root.intersect(&between).is_none());
+    }
+
+    #[test]
+    fn test_occlusion_agrees_with_trace() {
+        // bumpy terrain mesh
+        let mut terrain = Mesh::new(white_material());
+        for i in 0..8 {
+            for j in 0..8 {
+                let (x, z) = (f64::from(i) * 2.0 - 7.0, -f64::from(j) * 2.0 - 4.0);
+                terrain
+                    .positions
+                    .push(Point::new(x, -2.0 + ((x + z) * 0.7).sin(), z));
+            }
+        }
+        for i in 0..7 {
+            for j in 0..7 {
+                let corner = i * 8 + j;
+                for vertices in [
+                    [corner, corner + 8, corner + 9],
+                    [corner, corner + 9, corner + 1],
+                ] {
+                    terrain.faces.push(Face {
+                        vertices,
+                        normals: None,
+                        texture_coords: None,
+                        material: 0,
+                    });
+                }
+            }
+        }
+        terrain.build_bvh();
+
+        // stretched ellipsoid, distances along world rays differ from object space
+        let ellipsoid = Transformed::new(
+            Sphere::new(Point::zero(), 1.0, white_material()),
+            Matrix4::translation(Vector3::new(2.0, 1.0, -9.0)) * Matrix4::scaling(3.0, 1.0, 0.5),
+        );
+
+        let mut spheres = Group::with_transform(
+            Matrix4::translation(Vector3::new(-3.0, 0.5, -12.0)) * Matrix4::scaling(2.0, 2.0, 2.0),
+        );
+        for k in 0..4 {
+            spheres.add(Box::new(Sphere::new(
+                Point::new(f64::from(k) - 1.5, 0.0, 0.0),
+                0.4,
+                white_material(),
+            )));
+        }
+        spheres.build_bvh();
+
+        let objects: Vec<Box<dyn Intersectable + Sync + Send>> =
+            vec![Box::new(terrain), Box::new(ellipsoid), Box::new(spheres)];
+        let mut rng = Rng::new(11);
+        let rays: Vec<(Ray, f64)> = (0..2000)
+            .map(|_| {
+                let ray = Ray {
+                    origin: Point::new(
+                        rng.next_f64() * 2.0 - 1.0,
+                        rng.next_f64() * 2.0,
+                        rng.next_f64() * 2.0 - 1.0,
+                    ),
+                    direction: Vector3::new(
+                        rng.next_f64() * 1.6 - 0.8,
+                        rng.next_f64() * 0.8 - 0.5,
+                        -1.0,
+                    )
+                    .normalize(),
+                };
+                (ray, rng.next_f64() * 25.0)
+            })
+            .collect();
+        let mut hits = [0; 3];
+        for (ray, max_distance) in &rays {
+            for (k, object) in objects.iter().enumerate() {
+                let closer = object.intersect(ray).is_some_and(|d| d < *max_distance);
+                assert_eq!(object.occludes(ray, *max_distance), closer);
+                if closer {
+                    hits[k] += 1;
+                }
+            }
+        }
+        // every object blocks some of the rays
+        assert!(hits.iter().all(|&h| h > 20), "{:?}", hits);
+
+        let mut scene = empty_scene();
+        scene.objects = objects;
+        for build in [
+            None,
+            Some(Scene::build_bvh as fn(&mut Scene)),
+            Some(Scene::build_kd_tree),
+        ] {
+            if let Some(build) = build {
+                build(&mut scene);
+            }
+            for (ray, max_distance) in &rays {
+                let closer = scene
+                    .trace(ray)
+                    .is_some_and(|hit| hit.distance < *max_distance);
+                assert_eq!(scene.occluded(ray, *max_distance), closer);
+            }
+        }
+    }
+
+    #[test]
+    fn test_packet_kernels_match_scalar() {
+        let mut rng = Rng::new(17);
+        let random_ray = |rng: &mut Rng| {
+            let mut direction = Vector3::new(
+                rng.next_f64() * 2.0 - 1.0,
+                rng.next_f64() * 2.0 - 1.0,
+                rng.next_f64() * 2.0 - 1.0,
+            );
+            // rays parallel to the slabs
+            match rng.next_u64() % 4 {
+                0 => direction.x = 0.0,
+                1 => direction.y = 0.0,
+                _ => {}
+            }
+            Ray {
+                origin: Point::new(
+                    rng.next_f64() * 6.0 - 3.0,
+                    rng.next_f64() * 6.0 - 3.0,
+                    rng.next_f64() * 6.0 - 3.0,
+                ),
+                direction: direction.normalize(),
+            }
+        };
+        let bounds = Aabb::new(Point::new(-1.0, -0.5, -2.0), Point::new(1.5, 1.0, 0.5));
+        let center = Point::new(0.3, -0.2, 0.1);
+        let normal = Vector3::new(0.2, -1.0, 0.4).normalize();
+
+        for n in 0..500 {
+            let rays: Vec<Ray> = (0..1 + n % PACKET_SIZE)
+                .map(|_| random_ray(&mut rng))
+                .collect();
+            let mut packet = RayPacket::new(&rays);
+            if n % 7 == 0 {
+                packet = packet.masked(&[true, false, true, false]);
+            }
+            let max_distance: [f64; PACKET_SIZE] = std::array::from_fn(|_| rng.next_f64() * 8.0);
+
+            // unused and masked lanes never hit
+            let sphere = packet::scalar::intersect_sphere(&packet, &center, 1.7);
+            let plane = packet::scalar::intersect_plane(&packet, &center, &normal);
+            let aabb = packet::scalar::intersect_aabb(&packet, &bounds, &max_distance);
+            for lane in 0..PACKET_SIZE {
+                if !packet.active[lane] {
+                    assert!(sphere[lane].is_none() && plane[lane].is_none() && !aabb[lane]);
+                }
+            }
+
+            #[cfg(target_arch = "x86_64")]
+            {
+                if is_x86_feature_detected!("avx") {
+                    let close = |a: [Option<f64>; PACKET_SIZE], b: [Option<f64>; PACKET_SIZE]| {
+                        a.iter().zip(&b).all(|pair| match pair {
+                            (Some(a), Some(b)) => (a - b).abs() <= 1e-12 * a.abs().max(1.0),
+                            (a, b) => a.is_none() && b.is_none(),
+                        })
+                    };
+                    // safe as the CPU supports AVX
+                    unsafe {
+                        assert!(close(
+                            packet::avx::intersect_sphere(&packet, &center, 1.7),
+                            sphere
+                        ));
+                        assert!(close(
+                            packet::avx::intersect_plane(&packet, &center, &normal),
+                            plane
+                        ));
+                        assert_eq!(
+                            packet::avx::intersect_aabb(&packet, &bounds, &max_distance),
+                            aabb
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_secondary_rays_keep_contact_shadows() {
+        // small ball resting on the floor
+        let mut scene = empty_scene();
+        scene.objects.push(Box::new(Plane {
+            normal: Vector3::new(0.0, -1.0, 0.0),
+            center: Point::zero(),
+            material: white_material(),
+        }));
+        scene.objects.push(Box::new(Sphere::new(
+            Point::new(0.0, 0.1, 0.0),
+            0.1,
+            white_material(),
+        )));
+
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let shadow_ray = Ray::secondary(&Point::new(0.05, 0.0, 0.0), &up, up);
+        assert!(shadow_ray.origin.y > 0.0 && shadow_ray.origin.y < 1e-4);
+        assert!(scene.occluded(&shadow_ray, f64::INFINITY));
+
+        // rays going into the surface start below it
+        let down = Ray::secondary(&Point::new(0.05, 0.0, 0.0), &up, -up);
+        assert!(down.origin.y < 0.0);
+        assert!(scene.trace(&down).is_none());
+    }
+
+    #[test]
+    fn test_tiled_render_matches_single_thread() {
+        // sizes are not multiples of the tile size and the filter
+        // spreads samples over tile borders
+        let mut scene = empty_scene();
+        scene.width = 70;
+        scene.height = 45;
+        scene.sampling = Sampling::new(4, SamplePattern::Stratified, Filter::Gaussian);
+        scene.bg_color = Color {
+            red: 0.2,
+            green: 0.4,
+            blue: 0.8,
+        };
+        scene.objects.push(Box::new(Sphere::new(
+            Point::new(0.3, -0.2, -3.0),
+            1.0,
+            white_material(),
+        )));
 
-    let sys_time = SystemTime::now();
-    let img: DynamicImage = render_in_threads(scene, 8);
-    println!(
-        "render_in_threads execution time {} ms",
-        sys_time.elapsed().unwrap().as_millis()
-    );
-    img.save("test-multithreaded.png").unwrap();
+        let single = render(&scene, 0, scene.width);
+        let tiled = render_in_threads(scene, 3);
+        for (x, y, pixel) in single.pixels() {
+            let other = tiled.get_pixel(x, y);
+            for c in 0..3 {
+                assert!(
+                    (i32::from(pixel[c]) - i32::from(other[c])).abs() <= 1,
+                    "pixel ({}, {}) differs",
+                    x,
+                    y
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,150 @@
+use crate::point::Point;
+use crate::vector3::Vector3;
+use std::ops::Mul;
+
+// 4x4 matrix in row-major order acting on column vectors,
+// points have implicit w = 1 and vectors w = 0
+#[derive(Debug, Copy, Clone)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Matrix4 {
+        Matrix4 { m }
+    }
+
+    pub fn identity() -> Matrix4 {
+        Matrix4::scaling(1.0, 1.0, 1.0)
+    }
+
+    pub fn translation(offset: Vector3) -> Matrix4 {
+        Matrix4 {
+            m: [
+                [1.0, 0.0, 0.0, offset.x],
+                [0.0, 1.0, 0.0, offset.y],
+                [0.0, 0.0, 1.0, offset.z],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn scaling(x: f64, y: f64, z: f64) -> Matrix4 {
+        Matrix4 {
+            m: [
+                [x, 0.0, 0.0, 0.0],
+                [0.0, y, 0.0, 0.0],
+                [0.0, 0.0, z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // returns counter-clockwise rotation around the axis by the angle in degrees
+    pub fn rotation(axis: Vector3, angle: f64) -> Matrix4 {
+        let Vector3 { x, y, z } = axis.normalize();
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        Matrix4 {
+            m: [
+                [
+                    t * x * x + cos,
+                    t * x * y - sin * z,
+                    t * x * z + sin * y,
+                    0.0,
+                ],
+                [
+                    t * x * y + sin * z,
+                    t * y * y + cos,
+                    t * y * z - sin * x,
+                    0.0,
+                ],
+                [
+                    t * x * z - sin * y,
+                    t * y * z + sin * x,
+                    t * z * z + cos,
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    // returns inverse by Gauss-Jordan elimination or None for singular matrix
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+        for col in 0..4 {
+            // partial pivoting for stability
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = a[col][col].recip();
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                let factor = a[row][col];
+                if row == col || factor == 0.0 {
+                    continue;
+                }
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+        Some(Matrix4 { m: inv })
+    }
+
+    pub fn transform_point(&self, point: &Point) -> Point {
+        let m = &self.m;
+        Point::new(
+            m[0][0] * point.x + m[0][1] * point.y + m[0][2] * point.z + m[0][3],
+            m[1][0] * point.x + m[1][1] * point.y + m[1][2] * point.z + m[1][3],
+            m[2][0] * point.x + m[2][1] * point.y + m[2][2] * point.z + m[2][3],
+        )
+    }
+
+    // transforms direction, translation does not apply
+    pub fn transform_vector(&self, vector: &Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3::new(
+            m[0][0] * vector.x + m[0][1] * vector.y + m[0][2] * vector.z,
+            m[1][0] * vector.x + m[1][1] * vector.y + m[1][2] * vector.z,
+            m[2][0] * vector.x + m[2][1] * vector.y + m[2][2] * vector.z,
+        )
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    // applies other first and then self
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+}
@@ -0,0 +1,388 @@
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::point::Point;
+use crate::rendering::{intersect_triangle, Intersectable, Intersection, Ray};
+use crate::scene::{Color, ColorType, Material, SurfaceType, TextureCoords};
+use crate::vector3::Vector3;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// triangle of a mesh, indices point into the mesh buffers
+pub struct Face {
+    pub vertices: [usize; 3],
+    // per-vertex normals for smooth shading, flat shading if None
+    pub normals: Option<[usize; 3]>,
+    pub texture_coords: Option<[usize; 3]>,
+    pub material: usize,
+}
+
+// triangle mesh sharing vertex, normal and texture coordinate buffers between faces,
+// faces are in counter-clockwise order when looking at their front side
+pub struct Mesh {
+    pub positions: Vec<Point>,
+    pub normals: Vec<Vector3>,
+    pub texture_coords: Vec<TextureCoords>,
+    pub faces: Vec<Face>,
+    pub materials: Vec<Material>,
+    // hierarchy over faces, see Mesh::build_bvh
+    pub bvh: Option<Bvh>,
+}
+
+impl Mesh {
+    // returns mesh without faces, all faces added later use the material 0
+    pub fn new(material: Material) -> Mesh {
+        Mesh {
+            positions: vec![],
+            normals: vec![],
+            texture_coords: vec![],
+            faces: vec![],
+            materials: vec![material],
+            bvh: None,
+        }
+    }
+
+    // loads Wavefront OBJ file, materials from the referenced MTL files
+    // are added after default_material which is used by faces without usemtl
+    pub fn load_obj<P: AsRef<Path>>(path: P, default_material: Material) -> io::Result<Mesh> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut mesh = Mesh::new(default_material);
+        let mut material_ids: HashMap<String, usize> = HashMap::new();
+        let mut current_material = 0;
+
+        for (line_num, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let keyword = match tokens.next() {
+                Some(k) if !k.starts_with('#') => k,
+                _ => continue,
+            };
+            let args: Vec<&str> = tokens.collect();
+            let invalid = |msg: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path.display(), line_num + 1, msg),
+                )
+            };
+
+            match keyword {
+                "v" => {
+                    let [x, y, z] = parse_floats(&args).ok_or_else(|| invalid("bad vertex"))?;
+                    mesh.positions.push(Point::new(x, y, z));
+                }
+                "vn" => {
+                    let [x, y, z] = parse_floats(&args).ok_or_else(|| invalid("bad normal"))?;
+                    mesh.normals.push(Vector3::new(x, y, z).normalize());
+                }
+                "vt" => {
+                    let u: f32 =
+                        parse_arg(&args, 0).ok_or_else(|| invalid("bad texture coords"))?;
+                    let v: f32 = parse_arg(&args, 1).unwrap_or(0.0);
+                    // obj v axis points up while image rows go down
+                    mesh.texture_coords.push(TextureCoords { x: u, y: 1.0 - v });
+                }
+                "f" => {
+                    let corners = args
+                        .iter()
+                        .map(|corner| mesh.parse_corner(corner))
+                        .collect::<Option<Vec<_>>>()
+                        .filter(|corners| corners.len() >= 3)
+                        .ok_or_else(|| invalid("bad face"))?;
+                    // triangulate polygons as a fan around the first corner
+                    for i in 1..corners.len() - 1 {
+                        let (a, b, c) = (corners[0], corners[i], corners[i + 1]);
+                        mesh.faces.push(Face {
+                            vertices: [a.0, b.0, c.0],
+                            texture_coords: match (a.1, b.1, c.1) {
+                                (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                                _ => None,
+                            },
+                            normals: match (a.2, b.2, c.2) {
+                                (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                                _ => None,
+                            },
+                            material: current_material,
+                        });
+                    }
+                }
+                "mtllib" => {
+                    for name in &args {
+                        for (name, material) in load_mtl(&dir.join(name))? {
+                            material_ids.insert(name, mesh.materials.len());
+                            mesh.materials.push(material);
+                        }
+                    }
+                }
+                "usemtl" => {
+                    current_material = args
+                        .first()
+                        .and_then(|name| material_ids.get(*name))
+                        .cloned()
+                        .unwrap_or(0);
+                }
+                // groups, objects and smoothing groups do not change the geometry
+                _ => {}
+            }
+        }
+
+        mesh.build_bvh();
+        Ok(mesh)
+    }
+
+    // builds bounding volume hierarchy over the faces,
+    // it has to be rebuilt after faces or positions change
+    pub fn build_bvh(&mut self) {
+        let bounds: Vec<Aabb> = self
+            .faces
+            .iter()
+            .map(|face| Aabb::from_points(&self.face_vertices(face)))
+            .collect();
+        self.bvh = Some(Bvh::new(&bounds));
+    }
+
+    // parses face corner "v", "v/vt", "v//vn" or "v/vt/vn" into zero based indices
+    fn parse_corner(&self, corner: &str) -> Option<(usize, Option<usize>, Option<usize>)> {
+        let mut parts = corner.split('/');
+        let vertex = resolve_index(parts.next()?, self.positions.len())?;
+        let texture_coords = match parts.next() {
+            Some(s) if !s.is_empty() => Some(resolve_index(s, self.texture_coords.len())?),
+            _ => None,
+        };
+        let normal = match parts.next() {
+            Some(s) if !s.is_empty() => Some(resolve_index(s, self.normals.len())?),
+            _ => None,
+        };
+
+        Some((vertex, texture_coords, normal))
+    }
+
+    fn face_vertices(&self, face: &Face) -> [Point; 3] {
+        [
+            self.positions[face.vertices[0]],
+            self.positions[face.vertices[1]],
+            self.positions[face.vertices[2]],
+        ]
+    }
+
+    // returns closest face with distance and barycentric coordinates of the hit
+    fn closest_face(&self, ray: &Ray) -> Option<(&Face, f64, f64, f64)> {
+        match self.bvh {
+            Some(ref bvh) => {
+                let (i, _) = bvh.closest(ray, |i| {
+                    intersect_triangle(ray, &self.face_vertices(&self.faces[i]))
+                        .map(|(distance, _, _)| distance)
+                })?;
+                let face = &self.faces[i];
+                intersect_triangle(ray, &self.face_vertices(face))
+                    .map(|(distance, u, v)| (face, distance, u, v))
+            }
+            None => self
+                .faces
+                .iter()
+                .filter_map(|face| {
+                    intersect_triangle(ray, &self.face_vertices(face))
+                        .map(|(distance, u, v)| (face, distance, u, v))
+                })
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap()),
+        }
+    }
+}
+
+impl Intersectable for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        self.closest_face(ray).map(|(_, distance, _, _)| distance)
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::from_points(
+            self.faces
+                .iter()
+                .flat_map(|face| face.vertices.iter().map(move |&v| &self.positions[v])),
+        )
+    }
+
+    fn occludes(&self, ray: &Ray, max_distance: f64) -> bool {
+        let occludes = |face: &Face| {
+            intersect_triangle(ray, &self.face_vertices(face))
+                .is_some_and(|(distance, _, _)| distance < max_distance)
+        };
+        match self.bvh {
+            Some(ref bvh) => bvh.any_hit(ray, max_distance, |i| occludes(&self.faces[i])),
+            None => self.faces.iter().any(occludes),
+        }
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let (face, distance, u, v) = self.closest_face(ray)?;
+        let w = 1.0 - u - v;
+
+        let normal = match face.normals {
+            Some([n0, n1, n2]) => {
+                (self.normals[n0] * w + self.normals[n1] * u + self.normals[n2] * v).normalize()
+            }
+            None => {
+                let [v0, v1, v2] = self.face_vertices(face);
+                (v1 - v0).cross(&(v2 - v0)).normalize()
+            }
+        };
+
+        let texture_coords = match face.texture_coords {
+            Some([t0, t1, t2]) => {
+                let [t0, t1, t2] = [
+                    self.texture_coords[t0],
+                    self.texture_coords[t1],
+                    self.texture_coords[t2],
+                ];
+                let (w, u, v) = (w as f32, u as f32, v as f32);
+                TextureCoords {
+                    x: t0.x * w + t1.x * u + t2.x * v,
+                    y: t0.y * w + t1.y * u + t2.y * v,
+                }
+            }
+            None => TextureCoords {
+                x: u as f32,
+                y: v as f32,
+            },
+        };
+
+        Some(
+            Intersection::new(
+                distance,
+                normal,
+                texture_coords,
+                &self.materials[face.material],
+            )
+            .facing(ray),
+        )
+    }
+}
+
+// converts one based obj index, negative indices count from the end
+fn resolve_index(s: &str, len: usize) -> Option<usize> {
+    let index: i64 = s.parse().ok()?;
+    let resolved = if index < 0 {
+        len as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved >= 0 && (resolved as usize) < len {
+        Some(resolved as usize)
+    } else {
+        None
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(args: &[&str], i: usize) -> Option<T> {
+    args.get(i).and_then(|s| s.parse().ok())
+}
+
+fn parse_floats(args: &[&str]) -> Option<[f64; 3]> {
+    Some([
+        parse_arg(args, 0)?,
+        parse_arg(args, 1)?,
+        parse_arg(args, 2)?,
+    ])
+}
+
+// material properties collected from mtl statements
+struct MtlMaterial {
+    diffuse: [f32; 3],
+    specular: [f32; 3],
+    texture: Option<String>,
+    dissolve: f32,
+    ior: f32,
+    illum: u32,
+}
+
+impl MtlMaterial {
+    // maps mtl properties onto the material model of the renderer,
+    // Kd becomes the color, reflective illumination models take
+    // reflect ratio from Ks and transparent materials refract with Ni
+    fn to_material(&self, dir: &Path) -> io::Result<Material> {
+        let color = match self.texture {
+            Some(ref name) => {
+                let texture = image::open(dir.join(name))
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                ColorType::Texture(texture)
+            }
+            None => ColorType::Color(Color {
+                red: self.diffuse[0],
+                green: self.diffuse[1],
+                blue: self.diffuse[2],
+            }),
+        };
+        let reflect_ratio = if self.illum >= 3 {
+            (self.specular[0] + self.specular[1] + self.specular[2]) / 3.0
+        } else {
+            0.0
+        };
+        let refractive_index = if self.dissolve < 1.0 { self.ior } else { 0.0 };
+
+        Ok(Material {
+            color,
+            surface_type: SurfaceType {
+                diffuse_albedo: if refractive_index > 0.0 { 0.0 } else { 1.0 },
+                reflect_ratio,
+                refractive_index,
+            },
+        })
+    }
+}
+
+fn load_mtl(path: &Path) -> io::Result<Vec<(String, Material)>> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut parsed: Vec<(String, MtlMaterial)> = vec![];
+
+    for line in fs::read_to_string(path)?.lines() {
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+        if keyword == "newmtl" {
+            parsed.push((
+                args.join(" "),
+                MtlMaterial {
+                    diffuse: [0.8, 0.8, 0.8],
+                    specular: [0.0, 0.0, 0.0],
+                    texture: None,
+                    dissolve: 1.0,
+                    ior: 1.0,
+                    illum: 2,
+                },
+            ));
+            continue;
+        }
+
+        let material = match parsed.last_mut() {
+            Some((_, m)) => m,
+            None => continue,
+        };
+        match keyword {
+            "Kd" => {
+                if let Some([r, g, b]) = parse_floats(&args) {
+                    material.diffuse = [r as f32, g as f32, b as f32];
+                }
+            }
+            "Ks" => {
+                if let Some([r, g, b]) = parse_floats(&args) {
+                    material.specular = [r as f32, g as f32, b as f32];
+                }
+            }
+            "map_Kd" => material.texture = args.last().map(|s| s.to_string()),
+            "d" => material.dissolve = parse_arg(&args, 0).unwrap_or(1.0),
+            "Tr" => material.dissolve = 1.0 - parse_arg(&args, 0).unwrap_or(0.0),
+            "Ni" => material.ior = parse_arg(&args, 0).unwrap_or(1.0),
+            "illum" => material.illum = parse_arg(&args, 0).unwrap_or(2),
+            _ => {}
+        }
+    }
+
+    parsed
+        .into_iter()
+        .map(|(name, m)| m.to_material(dir).map(|material| (name, material)))
+        .collect()
+}
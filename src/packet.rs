@@ -0,0 +1,334 @@
+use crate::aabb::Aabb;
+use crate::point::Point;
+use crate::rendering::Ray;
+use crate::vector3::Vector3;
+#[cfg(target_arch = "x86_64")]
+use std::sync::OnceLock;
+
+// number of rays traced together, one per f64 lane of a 256-bit register
+pub const PACKET_SIZE: usize = 4;
+
+// set of kernels a packet runs
+#[derive(Clone, Copy)]
+enum Kernels {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Avx,
+}
+
+impl Kernels {
+    // returns AVX kernels on x86_64 when the CPU has it,
+    // the CPU is checked only once
+    fn detect() -> Kernels {
+        #[cfg(target_arch = "x86_64")]
+        {
+            static HAS_AVX: OnceLock<bool> = OnceLock::new();
+            if *HAS_AVX.get_or_init(|| is_x86_feature_detected!("avx")) {
+                return Kernels::Avx;
+            }
+        }
+        Kernels::Scalar
+    }
+}
+
+// RayPacket holds up to PACKET_SIZE coherent rays, e.g. primary rays
+// of neighbouring pixels, in structure of arrays layout for SIMD; kernels
+// use AVX on x86_64 when the CPU has it and plain loops otherwise
+#[derive(Clone)]
+pub struct RayPacket {
+    pub rays: [Ray; PACKET_SIZE],
+    // lanes holding rays, kernels report no hits for the others
+    pub active: [bool; PACKET_SIZE],
+    origin: [[f64; PACKET_SIZE]; 3],
+    direction: [[f64; PACKET_SIZE]; 3],
+    kernels: Kernels,
+}
+
+impl RayPacket {
+    // panics if there are no rays or more than PACKET_SIZE
+    pub fn new(rays: &[Ray]) -> RayPacket {
+        assert!(!rays.is_empty() && rays.len() <= PACKET_SIZE);
+        let unused = Ray {
+            origin: Point::zero(),
+            direction: Vector3::new(0.0, 0.0, 0.0),
+        };
+        let lanes: [Ray; PACKET_SIZE] =
+            std::array::from_fn(|lane| *rays.get(lane).unwrap_or(&unused));
+        RayPacket {
+            rays: lanes,
+            active: std::array::from_fn(|lane| lane < rays.len()),
+            origin: [
+                lanes.map(|r| r.origin.x),
+                lanes.map(|r| r.origin.y),
+                lanes.map(|r| r.origin.z),
+            ],
+            direction: [
+                lanes.map(|r| r.direction.x),
+                lanes.map(|r| r.direction.y),
+                lanes.map(|r| r.direction.z),
+            ],
+            kernels: Kernels::detect(),
+        }
+    }
+
+    // returns the packet with only the lanes active in both
+    // this packet and the mask
+    pub fn masked(&self, mask: &[bool; PACKET_SIZE]) -> RayPacket {
+        let mut packet = self.clone();
+        for (active, &keep) in packet.active.iter_mut().zip(mask) {
+            *active &= keep;
+        }
+        packet
+    }
+
+    // returns for every lane whether the ray meets the box closer than
+    // its max distance, may report hits for rays touching the box edges
+    pub fn intersect_aabb(
+        &self,
+        bounds: &Aabb,
+        max_distance: &[f64; PACKET_SIZE],
+    ) -> [bool; PACKET_SIZE] {
+        match self.kernels {
+            // safe as the kernels are chosen only when the CPU supports AVX
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx => unsafe { avx::intersect_aabb(self, bounds, max_distance) },
+            Kernels::Scalar => scalar::intersect_aabb(self, bounds, max_distance),
+        }
+    }
+
+    // same as Sphere::intersect for every lane
+    pub fn intersect_sphere(&self, center: &Point, radius_sq: f64) -> [Option<f64>; PACKET_SIZE] {
+        match self.kernels {
+            // safe as the kernels are chosen only when the CPU supports AVX
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx => unsafe { avx::intersect_sphere(self, center, radius_sq) },
+            Kernels::Scalar => scalar::intersect_sphere(self, center, radius_sq),
+        }
+    }
+
+    // same as Plane::intersect for every lane
+    pub fn intersect_plane(&self, center: &Point, normal: &Vector3) -> [Option<f64>; PACKET_SIZE] {
+        match self.kernels {
+            // safe as the kernels are chosen only when the CPU supports AVX
+            #[cfg(target_arch = "x86_64")]
+            Kernels::Avx => unsafe { avx::intersect_plane(self, center, normal) },
+            Kernels::Scalar => scalar::intersect_plane(self, center, normal),
+        }
+    }
+}
+
+// one lane at a time, used when the CPU has no AVX
+pub(crate) mod scalar {
+    use super::{RayPacket, PACKET_SIZE};
+    use crate::aabb::Aabb;
+    use crate::point::Point;
+    use crate::vector3::Vector3;
+
+    pub(crate) fn intersect_aabb(
+        packet: &RayPacket,
+        bounds: &Aabb,
+        max_distance: &[f64; PACKET_SIZE],
+    ) -> [bool; PACKET_SIZE] {
+        std::array::from_fn(|lane| {
+            packet.active[lane]
+                && bounds
+                    .intersect(&packet.rays[lane], max_distance[lane])
+                    .is_some()
+        })
+    }
+
+    pub(crate) fn intersect_sphere(
+        packet: &RayPacket,
+        center: &Point,
+        radius_sq: f64,
+    ) -> [Option<f64>; PACKET_SIZE] {
+        std::array::from_fn(|lane| {
+            if !packet.active[lane] {
+                return None;
+            }
+            let ray = &packet.rays[lane];
+            let l = *center - ray.origin;
+            let adj = l.dot(&ray.direction);
+            let d2 = l.dot(&l) - (adj * adj);
+            if d2 > radius_sq {
+                return None;
+            }
+            let d1 = (radius_sq - d2).sqrt();
+            let (t0, t1) = (adj - d1, adj + d1);
+            if t0 >= 0.0 {
+                Some(t0)
+            } else if t1 >= 0.0 {
+                Some(t1)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub(crate) fn intersect_plane(
+        packet: &RayPacket,
+        center: &Point,
+        normal: &Vector3,
+    ) -> [Option<f64>; PACKET_SIZE] {
+        std::array::from_fn(|lane| {
+            if !packet.active[lane] {
+                return None;
+            }
+            let ray = &packet.rays[lane];
+            let denom = normal.dot(&ray.direction);
+            if denom > 1e-6 {
+                let distance = (*center - ray.origin).dot(normal) / denom;
+                if distance >= 0.0 {
+                    return Some(distance);
+                }
+            }
+            None
+        })
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) mod avx {
+    use super::{RayPacket, PACKET_SIZE};
+    use crate::aabb::Aabb;
+    use crate::point::Point;
+    use crate::vector3::Vector3;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx")]
+    unsafe fn load(values: &[f64; PACKET_SIZE]) -> __m256d {
+        _mm256_loadu_pd(values.as_ptr())
+    }
+
+    // returns active lanes selected by the mask as distances, others as None
+    #[target_feature(enable = "avx")]
+    unsafe fn distances(
+        packet: &RayPacket,
+        t: __m256d,
+        mask: __m256d,
+    ) -> [Option<f64>; PACKET_SIZE] {
+        let mut values = [0.0; PACKET_SIZE];
+        _mm256_storeu_pd(values.as_mut_ptr(), t);
+        let bits = _mm256_movemask_pd(mask);
+        let mut result = [None; PACKET_SIZE];
+        for (lane, value) in values.iter().enumerate() {
+            if packet.active[lane] && bits & (1 << lane) != 0 {
+                result[lane] = Some(*value);
+            }
+        }
+        result
+    }
+
+    #[target_feature(enable = "avx")]
+    unsafe fn dot(a: [__m256d; 3], b: [__m256d; 3]) -> __m256d {
+        _mm256_add_pd(
+            _mm256_add_pd(_mm256_mul_pd(a[0], b[0]), _mm256_mul_pd(a[1], b[1])),
+            _mm256_mul_pd(a[2], b[2]),
+        )
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(crate) unsafe fn intersect_aabb(
+        packet: &RayPacket,
+        bounds: &Aabb,
+        max_distance: &[f64; PACKET_SIZE],
+    ) -> [bool; PACKET_SIZE] {
+        let mut enter = _mm256_setzero_pd();
+        let mut exit = load(max_distance);
+        let mins = [bounds.min.x, bounds.min.y, bounds.min.z];
+        let maxs = [bounds.max.x, bounds.max.y, bounds.max.z];
+        for axis in 0..3 {
+            let origin = load(&packet.origin[axis]);
+            let inv_direction = _mm256_div_pd(_mm256_set1_pd(1.0), load(&packet.direction[axis]));
+            let t0 = _mm256_mul_pd(
+                _mm256_sub_pd(_mm256_set1_pd(mins[axis]), origin),
+                inv_direction,
+            );
+            let t1 = _mm256_mul_pd(
+                _mm256_sub_pd(_mm256_set1_pd(maxs[axis]), origin),
+                inv_direction,
+            );
+            // NaN appears when the origin is on the slab plane and the ray is
+            // parallel to it, such lanes do not limit the span on this axis
+            let nan = _mm256_or_pd(
+                _mm256_cmp_pd(t0, t0, _CMP_UNORD_Q),
+                _mm256_cmp_pd(t1, t1, _CMP_UNORD_Q),
+            );
+            let near = _mm256_blendv_pd(
+                _mm256_min_pd(t0, t1),
+                _mm256_set1_pd(f64::NEG_INFINITY),
+                nan,
+            );
+            let far = _mm256_blendv_pd(_mm256_max_pd(t0, t1), _mm256_set1_pd(f64::INFINITY), nan);
+            enter = _mm256_max_pd(enter, near);
+            exit = _mm256_min_pd(exit, far);
+        }
+
+        let bits = _mm256_movemask_pd(_mm256_cmp_pd(enter, exit, _CMP_LE_OQ));
+        std::array::from_fn(|lane| packet.active[lane] && bits & (1 << lane) != 0)
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(crate) unsafe fn intersect_sphere(
+        packet: &RayPacket,
+        center: &Point,
+        radius_sq: f64,
+    ) -> [Option<f64>; PACKET_SIZE] {
+        let direction = [
+            load(&packet.direction[0]),
+            load(&packet.direction[1]),
+            load(&packet.direction[2]),
+        ];
+        let l = [
+            _mm256_sub_pd(_mm256_set1_pd(center.x), load(&packet.origin[0])),
+            _mm256_sub_pd(_mm256_set1_pd(center.y), load(&packet.origin[1])),
+            _mm256_sub_pd(_mm256_set1_pd(center.z), load(&packet.origin[2])),
+        ];
+        let adj = dot(l, direction);
+        let d2 = _mm256_sub_pd(dot(l, l), _mm256_mul_pd(adj, adj));
+        let radius_sq = _mm256_set1_pd(radius_sq);
+        let hit = _mm256_cmp_pd(d2, radius_sq, _CMP_LE_OQ);
+        let d1 = _mm256_sqrt_pd(_mm256_sub_pd(radius_sq, d2));
+        let t0 = _mm256_sub_pd(adj, d1);
+        let t1 = _mm256_add_pd(adj, d1);
+
+        // the near hit unless it is behind the origin
+        let zero = _mm256_setzero_pd();
+        let t0_front = _mm256_cmp_pd(t0, zero, _CMP_GE_OQ);
+        let t1_front = _mm256_cmp_pd(t1, zero, _CMP_GE_OQ);
+        let t = _mm256_blendv_pd(t1, t0, t0_front);
+        distances(
+            packet,
+            t,
+            _mm256_and_pd(hit, _mm256_or_pd(t0_front, t1_front)),
+        )
+    }
+
+    #[target_feature(enable = "avx")]
+    pub(crate) unsafe fn intersect_plane(
+        packet: &RayPacket,
+        center: &Point,
+        normal: &Vector3,
+    ) -> [Option<f64>; PACKET_SIZE] {
+        let normal = [
+            _mm256_set1_pd(normal.x),
+            _mm256_set1_pd(normal.y),
+            _mm256_set1_pd(normal.z),
+        ];
+        let direction = [
+            load(&packet.direction[0]),
+            load(&packet.direction[1]),
+            load(&packet.direction[2]),
+        ];
+        let v = [
+            _mm256_sub_pd(_mm256_set1_pd(center.x), load(&packet.origin[0])),
+            _mm256_sub_pd(_mm256_set1_pd(center.y), load(&packet.origin[1])),
+            _mm256_sub_pd(_mm256_set1_pd(center.z), load(&packet.origin[2])),
+        ];
+        let denom = dot(normal, direction);
+        let facing = _mm256_cmp_pd(denom, _mm256_set1_pd(1e-6), _CMP_GT_OQ);
+        let t = _mm256_div_pd(dot(v, normal), denom);
+        let front = _mm256_cmp_pd(t, _mm256_setzero_pd(), _CMP_GE_OQ);
+        distances(packet, t, _mm256_and_pd(facing, front))
+    }
+}
@@ -0,0 +1,141 @@
+// real roots of polynomials up to the fourth degree,
+// every solver returns roots in ascending order
+
+use std::f64::consts::PI;
+
+const EPSILON: f64 = 1e-12;
+
+// returns roots of a * t^2 + b * t + c in ascending order
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a.abs() < EPSILON {
+        if b.abs() < EPSILON {
+            return None;
+        }
+        let t = -c / b;
+        return Some((t, t));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // avoid cancellation when b and the root of discriminant are close
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+// returns real roots of t^3 + a * t^2 + b * t + c
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // substitute t = x - a / 3 to get depressed cubic x^3 + p * x + q
+    let shift = a / 3.0;
+    let p = b - a * shift;
+    let q = 2.0 * shift * shift * shift - b * shift + c;
+
+    let mut roots = if p.abs() < EPSILON {
+        vec![-q.cbrt()]
+    } else {
+        let discriminant = q * q / 4.0 + p * p * p / 27.0;
+        if discriminant > EPSILON {
+            // one real root, Cardano's formula
+            let sqrt_d = discriminant.sqrt();
+            vec![(-q / 2.0 + sqrt_d).cbrt() + (-q / 2.0 - sqrt_d).cbrt()]
+        } else if discriminant < -EPSILON {
+            // three real roots, trigonometric method
+            let r = 2.0 * (-p / 3.0).sqrt();
+            let phi = ((3.0 * q) / (p * r)).clamp(-1.0, 1.0).acos() / 3.0;
+            (0..3)
+                .map(|k| r * (phi - 2.0 * PI * f64::from(k) / 3.0).cos())
+                .collect()
+        } else {
+            // double root
+            let u = (-q / 2.0).cbrt();
+            vec![2.0 * u, -u]
+        }
+    };
+
+    for root in roots.iter_mut() {
+        *root -= shift;
+    }
+    roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    roots
+}
+
+// returns real roots of a * t^4 + b * t^3 + c * t^2 + d * t + e
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a.abs() < EPSILON {
+        let mut roots = solve_cubic_general(b, c, d, e);
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        return roots;
+    }
+
+    // Ferrari's method on the depressed quartic y^4 + p * y^2 + q * y + r
+    // with t = y - b / 4a, roots are refined with Newton iterations afterwards
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+    let shift = b / 4.0;
+    let shift2 = shift * shift;
+    let p = c - 6.0 * shift2;
+    let q = d - 2.0 * c * shift + 8.0 * shift * shift2;
+    let r = e - d * shift + c * shift2 - 3.0 * shift2 * shift2;
+
+    let mut roots = vec![];
+    if q.abs() < EPSILON {
+        // biquadratic, solve for y^2
+        if let Some((z0, z1)) = solve_quadratic(1.0, p, r) {
+            for z in [z0, z1] {
+                if z >= 0.0 {
+                    roots.push(z.sqrt());
+                    roots.push(-z.sqrt());
+                }
+            }
+        }
+    } else {
+        // resolvent cubic has a positive root m when q is not zero
+        let m = solve_cubic(2.0 * p, p * p - 4.0 * r, -q * q)
+            .into_iter()
+            .fold(f64::NEG_INFINITY, f64::max);
+        if m > 0.0 {
+            let sqrt_m = m.sqrt();
+            for (sign, s) in [(1.0, sqrt_m), (-1.0, -sqrt_m)] {
+                // y^2 -+ sqrt(m) * y + (p + m) / 2 +- q / (2 * sqrt(m)) = 0
+                let constant = (p + m) / 2.0 + sign * q / (2.0 * sqrt_m);
+                if let Some((y0, y1)) = solve_quadratic(1.0, -s, constant) {
+                    roots.push(y0);
+                    roots.push(y1);
+                }
+            }
+        }
+    }
+
+    let mut roots: Vec<f64> = roots
+        .into_iter()
+        .map(|y| polish_quartic_root(y - shift, b, c, d, e))
+        .collect();
+    roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    roots
+}
+
+fn solve_cubic_general(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if a.abs() < EPSILON {
+        return solve_quadratic(b, c, d)
+            .map(|(t0, t1)| vec![t0, t1])
+            .unwrap_or_default();
+    }
+    solve_cubic(b / a, c / a, d / a)
+}
+
+// refines root of t^4 + b * t^3 + c * t^2 + d * t + e with Newton's method
+fn polish_quartic_root(mut t: f64, b: f64, c: f64, d: f64, e: f64) -> f64 {
+    for _ in 0..4 {
+        let value = (((t + b) * t + c) * t + d) * t + e;
+        let derivative = ((4.0 * t + 3.0 * b) * t + 2.0 * c) * t + d;
+        if derivative.abs() < EPSILON {
+            break;
+        }
+        let step = value / derivative;
+        t -= step;
+        if step.abs() < EPSILON * t.abs().max(1.0) {
+            break;
+        }
+    }
+    t
+}
@@ -1,67 +1,186 @@
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::film::Film;
+use crate::kdtree::KdTree;
+use crate::packet::{RayPacket, PACKET_SIZE};
 use crate::point::Point;
-use crate::scene::{Color, Material, Plane, Scene, Sphere, TextureCoords};
+use crate::polynomial::{solve_quadratic, solve_quartic};
+use crate::sampling::{Rng, SampleStats};
+use crate::scene::{
+    AxisAlignedBox, Color, Cone, Cylinder, Disk, Material, Plane, Quad, Scene, Sphere,
+    TextureCoords, Torus, Triangle,
+};
 use crate::vector3::Vector3;
 use image::*;
 use std::f32;
 use std::fmt;
-use std::sync::Arc;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 pub fn render(scene: &Scene, start_width: u32, end_width: u32) -> DynamicImage {
-    let mut image = DynamicImage::new_rgb8(end_width - start_width, scene.height);
-    for x in start_width..end_width {
-        let x_on_image = x - start_width;
-        for y in 0..scene.height {
-            let ray = Ray::create_prime(x, y, scene);
-            image.put_pixel(x_on_image, y, get_color(scene, &ray, 0).to_rgba());
+    // samples of neighbour columns within the filter radius
+    // contribute to the edges of the stripe too
+    let margin = filter_margin(scene);
+    let mut film = Film::new(start_width, 0, end_width - start_width, scene.height);
+    sample_region(
+        scene,
+        start_width.saturating_sub(margin)..(end_width + margin).min(scene.width),
+        0..scene.height,
+        &mut film,
+    );
+
+    film.to_image()
+}
+
+// number of pixels around a pixel its samples contribute to
+fn filter_margin(scene: &Scene) -> u32 {
+    (scene.sampling.filter.radius() - 0.5).max(0.0).ceil() as u32
+}
+
+// side of the square blocks of pixels whose primary rays are traced together
+const BLOCK_SIZE: u32 = 2;
+
+fn sample_region(scene: &Scene, xs: Range<u32>, ys: Range<u32>, film: &mut Film) {
+    let camera_basis = scene.camera.basis();
+    for block_y in ys.clone().step_by(BLOCK_SIZE as usize) {
+        for block_x in xs.clone().step_by(BLOCK_SIZE as usize) {
+            sample_block(
+                scene,
+                &camera_basis,
+                block_x..(block_x + BLOCK_SIZE).min(xs.end),
+                block_y..(block_y + BLOCK_SIZE).min(ys.end),
+                film,
+            );
         }
     }
+}
 
-    image
+// pixel of a block being sampled
+struct PixelSamples {
+    x: u32,
+    y: u32,
+    rng: Rng,
+    stats: SampleStats,
+    // positions of the next batch of samples
+    positions: Vec<(f64, f64)>,
+}
+
+// samples the block of neighbouring pixels in batches, primary rays
+// of the pixels are coherent and traced in packets, each packet takes
+// one sample of every pixel
+fn sample_block(
+    scene: &Scene,
+    camera_basis: &(Vector3, Vector3, Vector3),
+    xs: Range<u32>,
+    ys: Range<u32>,
+    film: &mut Film,
+) {
+    let mut pixels: Vec<PixelSamples> = ys
+        .flat_map(|y| xs.clone().map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let mut rng = Rng::for_pixel(x, y);
+            let positions = scene.sampling.positions(&mut rng);
+            PixelSamples {
+                x,
+                y,
+                rng,
+                stats: SampleStats::new(),
+                positions,
+            }
+        })
+        .collect();
+
+    loop {
+        // (sample index within the pixel, pixel index, image x, image y, ray)
+        let mut samples: Vec<(usize, usize, f64, f64, Option<Ray>)> = vec![];
+        for (p, pixel) in pixels.iter_mut().enumerate() {
+            for (k, (sample_x, sample_y)) in pixel.positions.drain(..).enumerate() {
+                let image_x = f64::from(pixel.x) + sample_x;
+                let image_y = f64::from(pixel.y) + sample_y;
+                let ray = Ray::create_prime(image_x, image_y, scene, camera_basis, &mut pixel.rng);
+                samples.push((k, p, image_x, image_y, ray));
+            }
+        }
+        if samples.is_empty() {
+            break;
+        }
+        // interleave the pixels so that packets take one sample of every
+        // pixel, the sort is stable
+        samples.sort_by_key(|sample| sample.0);
+
+        let rays: Vec<Ray> = samples.iter().filter_map(|s| s.4).collect();
+        let mut colors = Vec::with_capacity(rays.len());
+        for chunk in rays.chunks(PACKET_SIZE) {
+            let packet = RayPacket::new(chunk);
+            let hits = scene.trace_packet(&packet);
+            for (ray, hit) in chunk.iter().zip(hits.iter()) {
+                colors.push(match hit {
+                    Some(hit) => shade(scene, ray, hit, 0),
+                    None => scene.bg_color,
+                });
+            }
+        }
+
+        let mut colors = colors.into_iter();
+        for (_, p, image_x, image_y, ray) in samples {
+            // samples outside of the lens image stay black
+            let color = match ray {
+                Some(_) => colors.next().unwrap(),
+                None => Color {
+                    red: 0.0,
+                    green: 0.0,
+                    blue: 0.0,
+                },
+            };
+            pixels[p].stats.add(f64::from(color.luminance()));
+            film.add_sample(image_x, image_y, color, &scene.sampling.filter);
+        }
+
+        // keep sampling noisy pixels, flat areas stop after the first batch
+        for pixel in &mut pixels {
+            let extra = scene.sampling.extra_samples(&pixel.stats);
+            pixel.positions = scene.sampling.extra_positions(extra, &mut pixel.rng);
+        }
+    }
 }
 
 fn get_color(scene: &Scene, ray: &Ray, depth: u32) -> Color {
-    let mut color = Color {
-        red: 0.0,
-        blue: 0.0,
-        green: 0.0,
-    };
     // max depth
     if depth > 5 {
         return scene.bg_color;
     }
 
-    let material: &Material;
-    let hit_point: Point;
-    let surface_normal: Vector3;
-    let texture_coords: TextureCoords;
-
-    if let Some(v) = scene.trace(&ray) {
-        material = v.obj.material();
-        hit_point = ray.origin + (ray.direction * v.distance);
-        surface_normal = v.obj.surface_normal(&hit_point);
-        texture_coords = v.obj.texture_coords(&hit_point);
-    } else {
-        return scene.bg_color;
+    match scene.trace(ray) {
+        Some(hit) => shade(scene, ray, &hit, depth),
+        None => scene.bg_color,
     }
+}
+
+// returns color of the hit seen along the ray
+fn shade(scene: &Scene, ray: &Ray, hit: &Intersection, depth: u32) -> Color {
+    let mut color = Color {
+        red: 0.0,
+        blue: 0.0,
+        green: 0.0,
+    };
+    let material = hit.material;
+    let hit_point = ray.origin + (ray.direction * hit.distance);
+    let surface_normal = hit.normal;
+    let texture_coords = hit.texture_coords;
 
     if material.surface_type.diffuse_albedo > 0.0 {
         let light_reflected = material.surface_type.diffuse_albedo / std::f32::consts::PI;
         for light in &scene.lights {
             let direction_to_light = light.direction(&hit_point);
 
-            let shadow_ray = Ray {
-                origin: hit_point + (surface_normal),
-                direction: direction_to_light,
-            };
+            let shadow_ray = Ray::secondary(&hit_point, &surface_normal, direction_to_light);
 
-            let shadow_intersection = scene.trace(&shadow_ray);
-            let light_intensity = if shadow_intersection.is_none()
-                || shadow_intersection.unwrap().distance > light.distance(&hit_point)
-            {
-                light.intensity(&hit_point)
-            } else {
+            let light_intensity = if scene.occluded(&shadow_ray, light.distance(&hit_point)) {
                 0.0
+            } else {
+                light.intensity(&hit_point)
             };
             let light_power =
                 (surface_normal.dot(&direction_to_light) as f32).max(0.0) * light_intensity;
@@ -72,7 +191,6 @@ fn get_color(scene: &Scene, ray: &Ray, depth: u32) -> Color {
     }
 
     if material.surface_type.refractive_index > 0.0 {
-        let reflection_color: Color;
         let mut refraction_color = Color {
             red: 0.0,
             green: 0.0,
@@ -84,29 +202,23 @@ fn get_color(scene: &Scene, ray: &Ray, depth: u32) -> Color {
             if let Some(dir) =
                 ray.refract(&surface_normal, 1.0, material.surface_type.refractive_index)
             {
-                let refracted_ray = Ray {
-                    origin: if ray.direction.dot(&surface_normal) < 0.0 {
-                        hit_point + surface_normal
-                    } else {
-                        hit_point - surface_normal
-                    },
-
-                    direction: dir,
-                };
+                let refracted_ray = Ray::secondary(&hit_point, &surface_normal, dir);
                 refraction_color = get_color(scene, &refracted_ray, depth + 1);
             }
         }
-        let reflected_ray = Ray {
-            origin: hit_point + (surface_normal),
-            direction: ray.reflect_direction(&surface_normal),
-        };
-        reflection_color = get_color(scene, &reflected_ray, depth + 1);
+        let reflected_ray = Ray::secondary(
+            &hit_point,
+            &surface_normal,
+            ray.reflect_direction(&surface_normal),
+        );
+        let reflection_color = get_color(scene, &reflected_ray, depth + 1);
         color = color + reflection_color * coeff_r + refraction_color * (1.0 - coeff_r);
     } else if material.surface_type.reflect_ratio > 0.0 {
-        let reflected_ray = Ray {
-            origin: hit_point + (surface_normal),
-            direction: ray.reflect_direction(&surface_normal),
-        };
+        let reflected_ray = Ray::secondary(
+            &hit_point,
+            &surface_normal,
+            ray.reflect_direction(&surface_normal),
+        );
         color = color
             + material.surface_type.reflect_ratio * get_color(scene, &reflected_ray, depth + 1);
     }
@@ -115,38 +227,125 @@ fn get_color(scene: &Scene, ray: &Ray, depth: u32) -> Color {
 }
 
 pub trait Intersectable {
+    // returns distance to the closest hit
     fn intersect(&self, ray: &Ray) -> Option<f64>;
+    // returns closest hit with the surface details at the hit point
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>>;
+    // returns axis-aligned box containing the object, infinite for unbounded objects
+    fn bounds(&self) -> Aabb;
+    // returns true if the ray hits the object closer than max_distance,
+    // objects can stop at any hit instead of looking for the closest one
+    fn occludes(&self, ray: &Ray, max_distance: f64) -> bool {
+        self.intersect(ray).is_some_and(|d| d < max_distance)
+    }
+    // returns distance to the closest hit for every active ray of the packet,
+    // objects with SIMD kernels test all lanes at once
+    fn intersect_packet(&self, packet: &RayPacket) -> [Option<f64>; PACKET_SIZE] {
+        std::array::from_fn(|lane| {
+            if packet.active[lane] {
+                self.intersect(&packet.rays[lane])
+            } else {
+                None
+            }
+        })
+    }
+    // returns true for solids with spans
+    fn is_solid(&self) -> bool {
+        false
+    }
+    // returns spans of the ray inside the solid sorted by distance, hits at both
+    // ends have normals pointing out of the solid and may be behind the origin,
+    // spans fully behind the origin may be left out;
+    // None for objects without inside like planes and meshes
+    fn spans(&self, _ray: &Ray) -> Option<Vec<Span<'_>>> {
+        None
+    }
+}
+
+// shares one object, e.g. a loaded mesh, between many transformed instances
+impl<T: Intersectable + ?Sized> Intersectable for Arc<T> {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        (**self).intersect(ray)
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        (**self).intersection(ray)
+    }
+
+    fn bounds(&self) -> Aabb {
+        (**self).bounds()
+    }
+
+    fn occludes(&self, ray: &Ray, max_distance: f64) -> bool {
+        (**self).occludes(ray, max_distance)
+    }
+
+    fn intersect_packet(&self, packet: &RayPacket) -> [Option<f64>; PACKET_SIZE] {
+        (**self).intersect_packet(packet)
+    }
+
+    fn is_solid(&self) -> bool {
+        (**self).is_solid()
+    }
+
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span<'_>>> {
+        (**self).spans(ray)
+    }
+}
+
+// Surface describes a single primitive at a point on it
+pub trait Surface {
     fn surface_normal(&self, point: &Point) -> Vector3;
     fn material(&self) -> &Material;
     fn texture_coords(&self, point: &Point) -> TextureCoords;
 }
 
+#[derive(Debug, Copy, Clone)]
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector3,
 }
 
+// offset of secondary ray origins relative to the hit point coordinates,
+// larger than errors of the intersection routines including sphere tracing
+const SECONDARY_RAY_OFFSET: f64 = 1e-5;
+
 impl Ray {
-    pub fn create_prime(x: u32, y: u32, scene: &Scene) -> Ray {
-        // sensor dimension and position
-        // the 2x2 sensor 1 unit from the camera
-        // with coordinates (-1.0…1.0, -1.0…1.0)
-        assert!(scene.width > scene.height);
+    // returns ray through the image point (x, y) in pixels, pixel centers are at +0.5,
+    // or None if the point is outside of the camera lens image;
+    // camera_basis is the result of Camera::basis for the scene camera
+    pub fn create_prime(
+        x: f64,
+        y: f64,
+        scene: &Scene,
+        camera_basis: &(Vector3, Vector3, Vector3),
+        rng: &mut Rng,
+    ) -> Option<Ray> {
+        let u = x / scene.width as f64;
+        let v = y / scene.height as f64;
         let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
-        let sensor_x =
-            ((((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio) * fov_adjustment;
-        let sensor_y = (1.0 - ((y as f64 + 0.5) / scene.height as f64) * 2.0) * fov_adjustment;
-
-        Ray {
-            origin: Point::new(0.0, 0.0, 0.0),
-            direction: Vector3 {
-                x: sensor_x,
-                y: sensor_y,
-                z: -1.0,
-            }
-            .normalize(),
-        }
+
+        scene.camera.ray(camera_basis, u, v, aspect_ratio, rng)
+    }
+
+    // returns ray leaving the surface at the hit point, its origin is moved off
+    // the surface to the side the ray goes so it does not hit the surface
+    // again due to rounding, by an amount growing with the coordinates
+    // as the rounding errors do
+    pub fn secondary(hit_point: &Point, normal: &Vector3, direction: Vector3) -> Ray {
+        let magnitude = hit_point
+            .x
+            .abs()
+            .max(hit_point.y.abs())
+            .max(hit_point.z.abs())
+            .max(1.0);
+        let offset = *normal * (SECONDARY_RAY_OFFSET * magnitude);
+        let origin = if direction.dot(normal) < 0.0 {
+            *hit_point - offset
+        } else {
+            *hit_point + offset
+        };
+        Ray { origin, direction }
     }
 
     // returns reflection direction
@@ -167,9 +366,7 @@ impl Ray {
             // we are inside the surface
             normal = -normal;
             // swap the refraction indices
-            let tmp = ior_from;
-            ior_from = ior_to;
-            ior_to = tmp;
+            std::mem::swap(&mut ior_from, &mut ior_to);
         }
 
         // n = n1/n2
@@ -186,12 +383,10 @@ impl Ray {
 
     // returns coeff of reflected light
     fn fresnel(&self, normal: &Vector3, mut ior_from: f32, mut ior_to: f32) -> f32 {
-        let mut cosi = self.direction.dot(normal).min(1.0).max(-1.0) as f32;
+        let mut cosi = self.direction.dot(normal).clamp(-1.0, 1.0) as f32;
         if cosi > 0.0 {
             // swap the refraction indices
-            let tmp = ior_from;
-            ior_from = ior_to;
-            ior_to = tmp;
+            std::mem::swap(&mut ior_from, &mut ior_to);
         }
         let eta = ior_from / ior_to;
         let sint = eta * (1.0 - cosi * cosi).max(0.0).sqrt();
@@ -229,7 +424,7 @@ impl Intersectable for Sphere {
         let t1 = adj + d1;
 
         if t0 < 0.0 && t1 < 0.0 {
-            return None;
+            None
         } else if t0 < 0.0 {
             Some(t1)
         } else if t1 < 0.0 {
@@ -240,6 +435,43 @@ impl Intersectable for Sphere {
         }
     }
 
+    fn intersect_packet(&self, packet: &RayPacket) -> [Option<f64>; PACKET_SIZE] {
+        packet.intersect_sphere(&self.center, self.radius_sq)
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self))
+    }
+
+    fn bounds(&self) -> Aabb {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span<'_>>> {
+        let l = self.center - ray.origin;
+        let adj = l.dot(&ray.direction);
+        let d2 = l.dot(&l) - (adj * adj);
+        if d2 > self.radius_sq {
+            return Some(vec![]);
+        }
+        let d1 = (self.radius_sq - d2).sqrt();
+        if adj + d1 < 0.0 {
+            return Some(vec![]);
+        }
+        Some(vec![Span {
+            enter: Intersection::on_surface(ray, adj - d1, self),
+            exit: Intersection::on_surface(ray, adj + d1, self),
+        }])
+    }
+}
+
+impl Surface for Sphere {
     fn surface_normal(&self, hit_point: &Point) -> Vector3 {
         (*hit_point - self.center).normalize()
     }
@@ -257,6 +489,41 @@ impl Intersectable for Sphere {
     }
 }
 
+// returns texture axes lying in the plane with the given normal
+fn plane_texture_axes(normal: &Vector3) -> (Vector3, Vector3) {
+    let formard_vec = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    };
+    let up_vec = Vector3 {
+        x: 0.0,
+        y: 1.0,
+        z: 0.0,
+    };
+
+    let mut x_axis = normal.cross(&formard_vec);
+    if x_axis.length() == 0.0 {
+        x_axis = normal.cross(&up_vec);
+    }
+
+    let y_axis = normal.cross(&x_axis);
+
+    (x_axis, y_axis)
+}
+
+// returns distance to the plane through the point hit from either side
+fn intersect_two_sided_plane(ray: &Ray, point: &Point, normal: &Vector3) -> Option<f64> {
+    let denom = normal.dot(&ray.direction);
+    if denom.abs() > 1e-6 {
+        let distance = (*point - ray.origin).dot(normal) / denom;
+        if distance >= 0.0 {
+            return Some(distance);
+        }
+    }
+    None
+}
+
 impl Intersectable for Plane {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
         let normal = &self.normal;
@@ -264,7 +531,7 @@ impl Intersectable for Plane {
 
         if denom > 1e-6 {
             let v = self.center - ray.origin;
-            let distance = v.dot(&normal) / denom;
+            let distance = v.dot(normal) / denom;
 
             if distance >= 0.0 {
                 return Some(distance);
@@ -273,6 +540,21 @@ impl Intersectable for Plane {
         None
     }
 
+    fn intersect_packet(&self, packet: &RayPacket) -> [Option<f64>; PACKET_SIZE] {
+        packet.intersect_plane(&self.center, &self.normal)
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self))
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::infinite()
+    }
+}
+
+impl Surface for Plane {
     fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
         -self.normal
     }
@@ -281,36 +563,715 @@ impl Intersectable for Plane {
         &self.material
     }
     fn texture_coords(&self, point: &Point) -> TextureCoords {
-        let formard_vec = Vector3 {
-            x: 0.0,
-            y: 0.0,
-            z: 1.0,
-        };
-        let up_vec = Vector3 {
-            x: 0.0,
-            y: 1.0,
-            z: 0.0,
+        let (x_axis, y_axis) = plane_texture_axes(&self.normal);
+        let vec_to_point = *point - self.center;
+
+        TextureCoords {
+            x: vec_to_point.dot(&x_axis) as f32,
+            y: vec_to_point.dot(&y_axis) as f32,
+        }
+    }
+}
+
+impl AxisAlignedBox {
+    // returns distances where the ray enters and exits the box,
+    // enter is negative when the ray starts inside
+    fn slabs(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let mut enter = f64::NEG_INFINITY;
+        let mut exit = f64::INFINITY;
+        for &(origin, direction, min, max) in &[
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+        ] {
+            if direction == 0.0 {
+                // parallel to the slab, hit only from inside of it
+                if origin < min || origin > max {
+                    return None;
+                }
+                continue;
+            }
+            let inv_direction = direction.recip();
+            let t0 = (min - origin) * inv_direction;
+            let t1 = (max - origin) * inv_direction;
+            enter = enter.max(t0.min(t1));
+            exit = exit.min(t0.max(t1));
+        }
+
+        if enter > exit || exit < 0.0 {
+            None
+        } else {
+            Some((enter, exit))
+        }
+    }
+
+    // returns axis (0, 1, 2 for x, y, z) and side (1.0 or -1.0) of the face closest to the point
+    fn face(&self, point: &Point) -> (usize, f64) {
+        let center = Point::new(
+            (self.min.x + self.max.x) * 0.5,
+            (self.min.y + self.max.y) * 0.5,
+            (self.min.z + self.max.z) * 0.5,
+        );
+        let half_size = (self.max - self.min) * 0.5_f64;
+        let local = *point - center;
+        let scaled = [
+            local.x / half_size.x,
+            local.y / half_size.y,
+            local.z / half_size.z,
+        ];
+
+        let mut axis = 0;
+        for i in 1..3 {
+            if scaled[i].abs() > scaled[axis].abs() {
+                axis = i;
+            }
+        }
+
+        (axis, scaled[axis].signum())
+    }
+}
+
+impl Intersectable for AxisAlignedBox {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        // https://www.scratchapixel.com/lessons/3d-basic-rendering/minimal-ray-tracer-rendering-simple-shapes/ray-box-intersection
+        let (enter, exit) = self.slabs(ray)?;
+        if enter >= 0.0 {
+            Some(enter)
+        } else {
+            Some(exit)
+        }
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self))
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::new(self.min, self.max)
+    }
+
+    fn is_solid(&self) -> bool {
+        true
+    }
+
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span<'_>>> {
+        Some(
+            self.slabs(ray)
+                .map(|(enter, exit)| Span {
+                    enter: Intersection::on_surface(ray, enter, self),
+                    exit: Intersection::on_surface(ray, exit, self),
+                })
+                .into_iter()
+                .collect(),
+        )
+    }
+}
+
+impl Surface for AxisAlignedBox {
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        match self.face(hit_point) {
+            (0, side) => Vector3::new(side, 0.0, 0.0),
+            (1, side) => Vector3::new(0.0, side, 0.0),
+            (_, side) => Vector3::new(0.0, 0.0, side),
+        }
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    // every face is mapped to the whole texture as seen from outside of the box
+    fn texture_coords(&self, point: &Point) -> TextureCoords {
+        let size = self.max - self.min;
+        let from_min = *point - self.min;
+        let from_max = self.max - *point;
+        let (x, y) = match self.face(point) {
+            (0, side) if side > 0.0 => (from_max.z / size.z, from_max.y / size.y),
+            (0, _) => (from_min.z / size.z, from_max.y / size.y),
+            (1, side) if side > 0.0 => (from_min.x / size.x, from_min.z / size.z),
+            (1, _) => (from_min.x / size.x, from_max.z / size.z),
+            (_, side) if side > 0.0 => (from_min.x / size.x, from_max.y / size.y),
+            (_, _) => (from_max.x / size.x, from_max.y / size.y),
         };
 
-        let mut x_axis = self.normal.cross(&formard_vec);
-        if x_axis.length() == 0.0 {
-            x_axis = self.normal.cross(&up_vec);
+        TextureCoords {
+            x: x as f32,
+            y: y as f32,
+        }
+    }
+}
+
+fn disk_bounds(center: &Point, normal: &Vector3, radius: f64) -> Aabb {
+    // extent of the disk along every axis is radius * sin of the angle to the normal
+    let extent = Vector3::new(
+        radius * (1.0 - normal.x * normal.x).max(0.0).sqrt(),
+        radius * (1.0 - normal.y * normal.y).max(0.0).sqrt(),
+        radius * (1.0 - normal.z * normal.z).max(0.0).sqrt(),
+    );
+    Aabb::new(*center - extent, *center + extent)
+}
+
+impl Intersectable for Disk {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let distance = intersect_two_sided_plane(ray, &self.center, &self.normal)?;
+        let hit_point = ray.origin + (ray.direction * distance);
+        if (hit_point - self.center).norm() <= self.radius * self.radius {
+            Some(distance)
+        } else {
+            None
         }
+    }
 
-        let y_axis = self.normal.cross(&x_axis);
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self).facing(ray))
+    }
+
+    fn bounds(&self) -> Aabb {
+        disk_bounds(&self.center, &self.normal, self.radius)
+    }
+}
+
+impl Surface for Disk {
+    fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
+        self.normal
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
 
+    // the texture is stretched over the square around the disk
+    fn texture_coords(&self, point: &Point) -> TextureCoords {
+        let (x_axis, y_axis) = plane_texture_axes(&self.normal);
         let vec_to_point = *point - self.center;
+        let scale = 0.5 / self.radius;
 
         TextureCoords {
-            x: vec_to_point.dot(&x_axis) as f32,
-            y: vec_to_point.dot(&y_axis) as f32,
+            x: (0.5 + vec_to_point.dot(&x_axis.normalize()) * scale) as f32,
+            y: (0.5 + vec_to_point.dot(&y_axis.normalize()) * scale) as f32,
+        }
+    }
+}
+
+impl Quad {
+    // returns coordinates of the point along both edges, the quad spans 0.0…1.0
+    fn local_coords(&self, point: &Point) -> (f64, f64) {
+        let n = self.edge_u.cross(&self.edge_v);
+        let w = n * n.norm().recip();
+        let to_point = *point - self.corner;
+
+        (
+            w.dot(&to_point.cross(&self.edge_v)),
+            w.dot(&self.edge_u.cross(&to_point)),
+        )
+    }
+}
+
+impl Intersectable for Quad {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let distance = intersect_two_sided_plane(ray, &self.corner, &self.normal)?;
+        let (u, v) = self.local_coords(&(ray.origin + (ray.direction * distance)));
+        if (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v) {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self).facing(ray))
+    }
+
+    fn bounds(&self) -> Aabb {
+        let corner = self.corner;
+        Aabb::from_points(&[
+            corner,
+            corner + self.edge_u,
+            corner + self.edge_v,
+            corner + self.edge_u + self.edge_v,
+        ])
+    }
+}
+
+impl Surface for Quad {
+    fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
+        self.normal
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn texture_coords(&self, point: &Point) -> TextureCoords {
+        let (u, v) = self.local_coords(point);
+        TextureCoords {
+            x: u as f32,
+            y: v as f32,
+        }
+    }
+}
+
+// returns distance to the disk along the whole line of the ray, may be negative
+fn intersect_cap(ray: &Ray, base: &Point, axis: &Vector3, height: f64, radius: f64) -> Option<f64> {
+    let center = *base + *axis * height;
+    let denom = axis.dot(&ray.direction);
+    if denom.abs() <= 1e-6 {
+        return None;
+    }
+    let distance = (center - ray.origin).dot(axis) / denom;
+    let hit_point = ray.origin + (ray.direction * distance);
+    if (hit_point - center).norm() <= radius * radius {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+// returns span between the first and the last boundary of a convex solid
+// along the line of the ray, empty if the solid is behind the origin
+fn convex_spans<'a>(ray: &Ray, distances: &[f64], surface: &'a dyn Surface) -> Vec<Span<'a>> {
+    let enter = distances.iter().copied().fold(f64::INFINITY, f64::min);
+    let exit = distances.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if distances.len() < 2 || exit < 0.0 {
+        return vec![];
+    }
+    vec![Span {
+        enter: Intersection::on_surface(ray, enter, surface),
+        exit: Intersection::on_surface(ray, exit, surface),
+    }]
+}
+
+// returns the closest non negative distance
+fn closest_distance<I: IntoIterator<Item = f64>>(distances: I) -> Option<f64> {
+    distances
+        .into_iter()
+        .filter(|&d| d >= 0.0)
+        .min_by(|a, b| a.partial_cmp(b).unwrap())
+}
+
+// returns height along the axis and the radial vector of the point
+fn axial_coords(point: &Point, base: &Point, axis: &Vector3) -> (f64, Vector3) {
+    let to_point = *point - *base;
+    let height = to_point.dot(axis);
+    (height, to_point - *axis * height)
+}
+
+// returns texture coordinates of the unit direction mapped on a sphere
+pub(crate) fn spherical_texture_coords(direction: &Vector3) -> TextureCoords {
+    TextureCoords {
+        x: (0.5 + direction.z.atan2(direction.x) / (2.0 * std::f64::consts::PI)) as f32,
+        y: (direction.y.clamp(-1.0, 1.0).acos() / std::f64::consts::PI) as f32,
+    }
+}
+
+// returns cylindrical texture coordinates, x goes around the axis and y along it
+fn cylindrical_texture_coords(
+    radial: &Vector3,
+    axis: &Vector3,
+    height: f64,
+    total: f64,
+) -> TextureCoords {
+    let (u, v) = axis.orthonormal_basis();
+    let angle = radial.dot(&v).atan2(radial.dot(&u));
+    TextureCoords {
+        x: (0.5 + angle / (2.0 * std::f64::consts::PI)) as f32,
+        y: (1.0 - height / total) as f32,
+    }
+}
+
+// returns texture coordinates of the square around the cap disk
+fn cap_texture_coords(radial: &Vector3, axis: &Vector3, radius: f64) -> TextureCoords {
+    let (u, v) = axis.orthonormal_basis();
+    TextureCoords {
+        x: (0.5 + radial.dot(&u) / (2.0 * radius)) as f32,
+        y: (0.5 + radial.dot(&v) / (2.0 * radius)) as f32,
+    }
+}
+
+impl Cylinder {
+    // returns true if the point lies on the cap at the given height
+    // rather than on the side, by the smaller deviation from each surface
+    fn on_cap(&self, point: &Point, cap_height: f64) -> bool {
+        let (h, radial) = axial_coords(point, &self.base, &self.axis);
+        self.capped && (h - cap_height).abs() < (radial.length() - self.radius).abs()
+    }
+
+    // returns distances to the boundary along the whole line of the ray
+    fn boundary_distances(&self, ray: &Ray) -> Vec<f64> {
+        // project the ray onto the plane perpendicular to the axis
+        // and intersect the circle, then clip by the height
+        let (origin_h, origin_radial) = axial_coords(&ray.origin, &self.base, &self.axis);
+        let direction_h = ray.direction.dot(&self.axis);
+        let direction_radial = ray.direction - self.axis * direction_h;
+
+        let a = direction_radial.norm();
+        let b = 2.0 * direction_radial.dot(&origin_radial);
+        let c = origin_radial.norm() - self.radius * self.radius;
+        let mut distances: Vec<f64> = solve_quadratic(a, b, c)
+            .map(|(t0, t1)| vec![t0, t1])
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|t| (0.0..=self.height).contains(&(origin_h + direction_h * t)))
+            .collect();
+
+        if self.capped {
+            distances.extend(intersect_cap(ray, &self.base, &self.axis, 0.0, self.radius));
+            distances.extend(intersect_cap(
+                ray,
+                &self.base,
+                &self.axis,
+                self.height,
+                self.radius,
+            ));
+        }
+        distances
+    }
+}
+
+impl Intersectable for Cylinder {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        closest_distance(self.boundary_distances(ray))
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self))
+    }
+
+    fn bounds(&self) -> Aabb {
+        let top = self.base + self.axis * self.height;
+        disk_bounds(&self.base, &self.axis, self.radius).union(&disk_bounds(
+            &top,
+            &self.axis,
+            self.radius,
+        ))
+    }
+
+    fn is_solid(&self) -> bool {
+        self.capped
+    }
+
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span<'_>>> {
+        if !self.capped {
+            return None;
+        }
+        Some(convex_spans(ray, &self.boundary_distances(ray), self))
+    }
+}
+
+impl Surface for Cylinder {
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        if self.on_cap(hit_point, 0.0) {
+            -self.axis
+        } else if self.on_cap(hit_point, self.height) {
+            self.axis
+        } else {
+            axial_coords(hit_point, &self.base, &self.axis)
+                .1
+                .normalize()
+        }
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn texture_coords(&self, point: &Point) -> TextureCoords {
+        let (h, radial) = axial_coords(point, &self.base, &self.axis);
+        if self.on_cap(point, 0.0) || self.on_cap(point, self.height) {
+            cap_texture_coords(&radial, &self.axis, self.radius)
+        } else {
+            cylindrical_texture_coords(&radial, &self.axis, h, self.height)
+        }
+    }
+}
+
+impl Cone {
+    // returns distances to the boundary along the whole line of the ray
+    fn boundary_distances(&self, ray: &Ray) -> Vec<f64> {
+        // https://lousodrome.net/blog/light/2017/01/03/intersection-of-a-ray-and-a-cone/
+        // points of the infinite double cone with the apex at the origin
+        // satisfy (x · axis)^2 = cos^2 * |x|^2, clip it by the height
+        let apex = self.apex();
+        let down = -self.axis;
+        let cos2 =
+            self.height * self.height / (self.height * self.height + self.radius * self.radius);
+        let co = ray.origin - apex;
+        let d_down = ray.direction.dot(&down);
+        let co_down = co.dot(&down);
+
+        let a = d_down * d_down - cos2;
+        let b = 2.0 * (d_down * co_down - cos2 * ray.direction.dot(&co));
+        let c = co_down * co_down - cos2 * co.norm();
+        let mut distances: Vec<f64> = solve_quadratic(a, b, c)
+            .map(|(t0, t1)| vec![t0, t1])
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|t| (0.0..=self.height).contains(&(co_down + d_down * t)))
+            .collect();
+
+        if self.capped {
+            distances.extend(intersect_cap(ray, &self.base, &self.axis, 0.0, self.radius));
+        }
+        distances
+    }
+
+    // returns true if the point lies on the base cap rather than on the side
+    fn on_cap(&self, point: &Point) -> bool {
+        let (h, radial) = axial_coords(point, &self.base, &self.axis);
+        let side_radius = self.radius * (1.0 - h / self.height);
+        self.capped && h.abs() < (radial.length() - side_radius).abs()
+    }
+}
+
+impl Intersectable for Cone {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        closest_distance(self.boundary_distances(ray))
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self))
+    }
+
+    fn bounds(&self) -> Aabb {
+        disk_bounds(&self.base, &self.axis, self.radius).include(&self.apex())
+    }
+
+    fn is_solid(&self) -> bool {
+        self.capped
+    }
+
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span<'_>>> {
+        if !self.capped {
+            return None;
+        }
+        Some(convex_spans(ray, &self.boundary_distances(ray), self))
+    }
+}
+
+impl Surface for Cone {
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        if self.on_cap(hit_point) {
+            return -self.axis;
+        }
+        let radial = axial_coords(hit_point, &self.base, &self.axis).1;
+        if radial.length() == 0.0 {
+            // at the apex
+            return self.axis;
+        }
+        // perpendicular to the slope going from the base rim to the apex
+        (radial.normalize() * self.height + self.axis * self.radius).normalize()
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn texture_coords(&self, point: &Point) -> TextureCoords {
+        let (h, radial) = axial_coords(point, &self.base, &self.axis);
+        if self.on_cap(point) {
+            cap_texture_coords(&radial, &self.axis, self.radius)
+        } else {
+            cylindrical_texture_coords(&radial, &self.axis, h, self.height)
+        }
+    }
+}
+
+impl Torus {
+    // returns coordinates of the point in the frame of the torus
+    // with the axis as z
+    fn local_coords(&self, point: &Point) -> Vector3 {
+        let (u, v) = self.axis.orthonormal_basis();
+        let p = *point - self.center;
+        Vector3 {
+            x: p.dot(&u),
+            y: p.dot(&v),
+            z: p.dot(&self.axis),
+        }
+    }
+}
+
+impl Intersectable for Torus {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        // points of the torus satisfy (|p|^2 + R^2 - r^2)^2 = 4 * R^2 * (x^2 + y^2),
+        // substituting the ray gives a quartic in the distance;
+        // start from the point closest to the center to keep coefficients small
+        let shift = (self.center - ray.origin).dot(&ray.direction);
+        let (u, v) = self.axis.orthonormal_basis();
+        let o = self.local_coords(&(ray.origin + ray.direction * shift));
+        let d = Vector3 {
+            x: ray.direction.dot(&u),
+            y: ray.direction.dot(&v),
+            z: ray.direction.dot(&self.axis),
+        };
+
+        let r2 = self.major_radius * self.major_radius;
+        let e = o.norm() + r2 - self.minor_radius * self.minor_radius;
+        let f = o.dot(&d);
+        let roots = solve_quartic(
+            1.0,
+            4.0 * f,
+            4.0 * f * f + 2.0 * e - 4.0 * r2 * (d.x * d.x + d.y * d.y),
+            4.0 * e * f - 8.0 * r2 * (o.x * d.x + o.y * d.y),
+            e * e - 4.0 * r2 * (o.x * o.x + o.y * o.y),
+        );
+
+        closest_distance(roots.into_iter().map(|t| t + shift))
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self))
+    }
+
+    fn bounds(&self) -> Aabb {
+        let offset = self.axis * self.minor_radius;
+        let radius = self.major_radius + self.minor_radius;
+        disk_bounds(&(self.center + offset), &self.axis, radius).union(&disk_bounds(
+            &(self.center - offset),
+            &self.axis,
+            radius,
+        ))
+    }
+}
+
+impl Surface for Torus {
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        // from the center of the tube cross-section to the point
+        let (h, radial) = axial_coords(hit_point, &self.center, &self.axis);
+        let length = radial.length();
+        let ring = if length > 0.0 {
+            radial * (self.major_radius / length)
+        } else {
+            Vector3::new(0.0, 0.0, 0.0)
+        };
+        (radial - ring + self.axis * h).normalize()
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn texture_coords(&self, point: &Point) -> TextureCoords {
+        // x goes around the axis and y around the tube
+        let p = self.local_coords(point);
+        let around_axis = p.y.atan2(p.x);
+        let around_tube =
+            p.z.atan2((p.x * p.x + p.y * p.y).sqrt() - self.major_radius);
+        TextureCoords {
+            x: (0.5 + around_axis / (2.0 * std::f64::consts::PI)) as f32,
+            y: (0.5 + around_tube / (2.0 * std::f64::consts::PI)) as f32,
+        }
+    }
+}
+
+// returns distance to the triangle and barycentric coordinates (u, v)
+// of the hit point, weights of the vertices are (1 - u - v, u, v)
+pub fn intersect_triangle(ray: &Ray, vertices: &[Point; 3]) -> Option<(f64, f64, f64)> {
+    // https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+    let [v0, v1, v2] = *vertices;
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let p = ray.direction.cross(&edge2);
+    let det = edge1.dot(&p);
+    // the ray is parallel to the triangle plane
+    if det.abs() < 1e-10 {
+        return None;
+    }
+
+    let inv_det = det.recip();
+    let s = ray.origin - v0;
+    let u = s.dot(&p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(&edge1);
+    let v = ray.direction.dot(&q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge2.dot(&q) * inv_det;
+    if distance >= 0.0 {
+        Some((distance, u, v))
+    } else {
+        None
+    }
+}
+
+impl Intersectable for Triangle {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        intersect_triangle(ray, &self.vertices).map(|(distance, _, _)| distance)
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self).facing(ray))
+    }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::from_points(&self.vertices)
+    }
+}
+
+impl Surface for Triangle {
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        match self.normals {
+            Some([n0, n1, n2]) => {
+                let (w0, w1, w2) = self.barycentric(hit_point);
+                (n0 * w0 + n1 * w1 + n2 * w2).normalize()
+            }
+            None => self.normal,
+        }
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn texture_coords(&self, point: &Point) -> TextureCoords {
+        let (w0, w1, w2) = self.barycentric(point);
+        let [t0, t1, t2] = self.texture_coords;
+        let (w0, w1, w2) = (w0 as f32, w1 as f32, w2 as f32);
+        TextureCoords {
+            x: t0.x * w0 + t1.x * w1 + t2.x * w2,
+            y: t0.y * w0 + t1.y * w1 + t2.y * w2,
+        }
+    }
+}
+
+// keeps the closer of the current and the new hit of primitive i in every lane
+pub(crate) fn update_closest(
+    closest: &mut [Option<(usize, f64)>; PACKET_SIZE],
+    i: usize,
+    distances: &[Option<f64>; PACKET_SIZE],
+) {
+    for (closest, distance) in closest.iter_mut().zip(distances) {
+        if let Some(distance) = *distance {
+            if closest.is_none_or(|(_, d)| distance < d) {
+                *closest = Some((i, distance));
+            }
         }
     }
 }
 
 pub struct Intersection<'a> {
     pub distance: f64,
-    pub obj: &'a Box<dyn Intersectable + Sync + Send>,
+    pub normal: Vector3,
+    pub texture_coords: TextureCoords,
+    pub material: &'a Material,
+}
+
+// part of the ray inside a solid, see Intersectable::spans
+#[derive(Debug)]
+pub struct Span<'a> {
+    pub enter: Intersection<'a>,
+    pub exit: Intersection<'a>,
 }
 
 impl<'a> fmt::Debug for Intersection<'a> {
@@ -320,57 +1281,192 @@ impl<'a> fmt::Debug for Intersection<'a> {
 }
 
 impl<'a> Intersection<'a> {
-    pub fn new(distance: f64, obj: &'a Box<dyn Intersectable + Sync + Send>) -> Intersection {
-        Intersection { distance, obj }
+    pub fn new(
+        distance: f64,
+        normal: Vector3,
+        texture_coords: TextureCoords,
+        material: &'a Material,
+    ) -> Intersection<'a> {
+        Intersection {
+            distance,
+            normal,
+            texture_coords,
+            material,
+        }
+    }
+
+    // returns intersection with the surface at the distance along the ray
+    pub fn on_surface(ray: &Ray, distance: f64, surface: &'a dyn Surface) -> Intersection<'a> {
+        let hit_point = ray.origin + (ray.direction * distance);
+        Intersection {
+            distance,
+            normal: surface.surface_normal(&hit_point),
+            texture_coords: surface.texture_coords(&hit_point),
+            material: surface.material(),
+        }
+    }
+
+    // turns the normal of a surface visible from both sides against the
+    // ray, so its back side is lit like the front
+    pub fn facing(mut self, ray: &Ray) -> Intersection<'a> {
+        if self.normal.dot(&ray.direction) > 0.0 {
+            self.normal = -self.normal;
+        }
+        self
     }
 }
 
 impl Scene {
-    pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
-        self.objects
-            .iter()
-            .filter_map(|s| s.intersect(ray).map(|d| Intersection::new(d, s)))
-            .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())
-    }
-}
-
-pub fn render_in_threads(scene: Scene, threads_num: u32) -> DynamicImage {
-    // TODO use randomized blocks to render scene
-    let scene = Arc::new(scene);
-    let mut image = DynamicImage::new_rgb8(scene.width, scene.height);
-    let mut workers = vec![];
-    let mut images = vec![];
-    let div = scene.width % threads_num;
-    let stripe_size: u32 = if div == 0 {
-        scene.width / threads_num
-    } else {
-        scene.width / threads_num + 1
-    };
+    // builds bounding volume hierarchy over the objects,
+    // it has to be rebuilt after objects change
+    pub fn build_bvh(&mut self) {
+        let bounds: Vec<Aabb> = self.objects.iter().map(|o| o.bounds()).collect();
+        self.accelerator = Some(Box::new(Bvh::new(&bounds)));
+    }
 
-    for i in 0..threads_num {
-        let scene = Arc::clone(&scene);
-        workers.push(thread::spawn(move || -> (DynamicImage, u32, u32) {
-            let start_width = i * stripe_size;
-            let end_width = if (i + 1) * stripe_size > scene.width {
-                scene.width
-            } else {
-                (i + 1) * stripe_size
-            };
-            let image = render(&scene, start_width, end_width);
-            (image, start_width, end_width)
-        }));
+    // builds kd-tree over the objects instead of the hierarchy,
+    // it has to be rebuilt after objects change
+    pub fn build_kd_tree(&mut self) {
+        let bounds: Vec<Aabb> = self.objects.iter().map(|o| o.bounds()).collect();
+        self.accelerator = Some(Box::new(KdTree::new(&bounds)));
     }
 
-    for worker in workers {
-        let image = worker.join().unwrap();
-        images.push(image);
+    pub fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let closest = match self.accelerator {
+            Some(ref accelerator) => {
+                accelerator.closest(ray, &mut |i| self.objects[i].intersect(ray))
+            }
+            None => self
+                .objects
+                .iter()
+                .enumerate()
+                .filter_map(|(i, s)| s.intersect(ray).map(|d| (i, d)))
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap()),
+        };
+        closest.and_then(|(i, _)| self.objects[i].intersection(ray))
     }
 
-    for (i, v) in images.iter().enumerate() {
-        if !image.copy_from(&v.0, v.1, 0) {
-            panic!("image {} not copied", i);
+    // returns closest hits of the active rays of the packet, same as trace for every lane
+    pub fn trace_packet(&self, packet: &RayPacket) -> [Option<Intersection<'_>>; PACKET_SIZE] {
+        let intersect =
+            &mut |i: usize, packet: &RayPacket| self.objects[i].intersect_packet(packet);
+        let closest = match self.accelerator {
+            Some(ref accelerator) => accelerator.closest_packet(packet, intersect),
+            None => {
+                let mut closest = [None; PACKET_SIZE];
+                for i in 0..self.objects.len() {
+                    update_closest(&mut closest, i, &intersect(i, packet));
+                }
+                Some(closest)
+            }
+        };
+
+        std::array::from_fn(|lane| {
+            let ray = &packet.rays[lane];
+            match closest {
+                _ if !packet.active[lane] => None,
+                Some(ref closest) => {
+                    closest[lane].and_then(|(i, _)| self.objects[i].intersection(ray))
+                }
+                // the accelerator has no packet traversal
+                None => self.trace(ray),
+            }
+        })
+    }
+
+    // returns true if any object is hit closer than max_distance,
+    // cheaper than trace as it stops at the first hit
+    pub fn occluded(&self, ray: &Ray, max_distance: f64) -> bool {
+        match self.accelerator {
+            Some(ref accelerator) => accelerator.any_hit(ray, max_distance, &mut |i| {
+                self.objects[i].occludes(ray, max_distance)
+            }),
+            None => self.objects.iter().any(|o| o.occludes(ray, max_distance)),
+        }
+    }
+}
+
+// side of the square tiles the image is split into for worker threads
+const TILE_SIZE: u32 = 32;
+
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+// returns tiles covering the image ordered in a spiral from the center
+// outwards, so the middle of the image, usually the busiest part, is
+// rendered first and workers finish with cheap tiles of similar cost
+fn spiral_tiles(width: u32, height: u32) -> Vec<Tile> {
+    let columns = width.div_ceil(TILE_SIZE);
+    let rows = height.div_ceil(TILE_SIZE);
+    let mut tiles = vec![];
+    for row in 0..rows {
+        for column in 0..columns {
+            let x = column * TILE_SIZE;
+            let y = row * TILE_SIZE;
+            tiles.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(width - x),
+                height: TILE_SIZE.min(height - y),
+            });
         }
     }
 
-    image
+    // (ring around the center tile, angle within the ring)
+    let position = |tile: &Tile| {
+        let dx = f64::from(tile.x / TILE_SIZE) + 0.5 - f64::from(columns) / 2.0;
+        let dy = f64::from(tile.y / TILE_SIZE) + 0.5 - f64::from(rows) / 2.0;
+        (dx.abs().max(dy.abs()).ceil(), dy.atan2(dx))
+    };
+    tiles.sort_by(|a, b| position(a).partial_cmp(&position(b)).unwrap());
+    tiles
+}
+
+// samples pixels of the tile into a film covering the tile
+// and the neighbour pixels its samples contribute to
+fn render_tile(scene: &Scene, tile: &Tile) -> Film {
+    let margin = filter_margin(scene);
+    let start_x = tile.x.saturating_sub(margin);
+    let start_y = tile.y.saturating_sub(margin);
+    let end_x = (tile.x + tile.width + margin).min(scene.width);
+    let end_y = (tile.y + tile.height + margin).min(scene.height);
+    let mut film = Film::new(start_x, start_y, end_x - start_x, end_y - start_y);
+    sample_region(
+        scene,
+        tile.x..tile.x + tile.width,
+        tile.y..tile.y + tile.height,
+        &mut film,
+    );
+
+    film
+}
+
+// renders the whole image like render, builds the bounding volume
+// hierarchy unless the caller has built an accelerator for the scene
+pub fn render_in_threads(mut scene: Scene, threads_num: u32) -> DynamicImage {
+    if scene.accelerator.is_none() {
+        scene.build_bvh();
+    }
+    let scene = &scene;
+    let tiles = spiral_tiles(scene.width, scene.height);
+    let next_tile = AtomicUsize::new(0);
+    let film = Mutex::new(Film::new(0, 0, scene.width, scene.height));
+
+    thread::scope(|s| {
+        for _ in 0..threads_num.max(1) {
+            s.spawn(|| {
+                // every worker takes the next tile from the queue until it is empty
+                while let Some(tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                    let tile_film = render_tile(scene, tile);
+                    film.lock().unwrap().merge(&tile_film);
+                }
+            });
+        }
+    });
+
+    film.into_inner().unwrap().to_image()
 }
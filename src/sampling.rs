@@ -0,0 +1,282 @@
+// small and fast pseudo random generator (xorshift64*),
+// good enough for sample positions and reproducible between renders
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // scramble the seed with splitmix64 so that close seeds
+        // give unrelated sequences and the state is never zero
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Rng {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    // generator for a pixel, the same pixel always gets the same sequence
+    pub fn for_pixel(x: u32, y: u32) -> Rng {
+        Rng::new((u64::from(x) << 32) | u64::from(y))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // returns value in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// maps uniform [0, 1)² sample onto the unit disk preserving
+// stratification (Shirley-Chiu concentric mapping)
+pub fn sample_unit_disk(u: f64, v: f64) -> (f64, f64) {
+    let a = 2.0 * u - 1.0;
+    let b = 2.0 * v - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, std::f64::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (
+            b,
+            std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b),
+        )
+    };
+
+    (r * theta.cos(), r * theta.sin())
+}
+
+// placement of sub-pixel samples
+pub enum SamplePattern {
+    // independent uniformly distributed positions
+    Jittered,
+    // one random position in every cell of a square grid, samples per
+    // pixel is rounded to the closest square number, at least 4 for 2 or more
+    Stratified,
+}
+
+// reconstruction filter weighting samples by their distance from the pixel center,
+// a sample contributes to every pixel whose center is within the filter radius
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    // Mitchell-Netravali with B = C = 1/3
+    Mitchell,
+}
+
+impl Filter {
+    // returns filter support radius in pixels
+    pub fn radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    // returns weight of the sample at offset (dx, dy) from the pixel center
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f64) -> f64 {
+        let d = d.abs();
+        if d > self.radius() {
+            return 0.0;
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - d,
+            Filter::Gaussian => {
+                // shifted down to reach zero at the support edge
+                const ALPHA: f64 = 2.0;
+                let r = self.radius();
+                (-ALPHA * d * d).exp() - (-ALPHA * r * r).exp()
+            }
+            Filter::Mitchell => {
+                const B: f64 = 1.0 / 3.0;
+                const C: f64 = 1.0 / 3.0;
+                let d2 = d * d;
+                let d3 = d2 * d;
+                if d < 1.0 {
+                    ((12.0 - 9.0 * B - 6.0 * C) * d3
+                        + (-18.0 + 12.0 * B + 6.0 * C) * d2
+                        + (6.0 - 2.0 * B))
+                        / 6.0
+                } else {
+                    ((-B - 6.0 * C) * d3
+                        + (6.0 * B + 30.0 * C) * d2
+                        + (-12.0 * B - 48.0 * C) * d
+                        + (8.0 * B + 24.0 * C))
+                        / 6.0
+                }
+            }
+        }
+    }
+}
+
+// running mean and variance of sample values (Welford's algorithm)
+pub struct SampleStats {
+    pub count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl SampleStats {
+    pub fn new() -> SampleStats {
+        SampleStats {
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / f64::from(self.count);
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // returns standard error of the mean
+    pub fn standard_error(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        let variance = self.m2 / f64::from(self.count - 1);
+        (variance / f64::from(self.count)).sqrt()
+    }
+}
+
+impl Default for SampleStats {
+    fn default() -> SampleStats {
+        SampleStats::new()
+    }
+}
+
+// adaptive sampling takes batches of samples_per_pixel samples
+// until the pixel luminance converges or max_samples is reached,
+// batches have at least 2 samples to estimate the variance
+pub struct AdaptiveSampling {
+    pub max_samples: u32,
+    // pixel is done when the standard error of its mean luminance drops below
+    pub threshold: f64,
+}
+
+pub struct Sampling {
+    pub samples_per_pixel: u32,
+    pub pattern: SamplePattern,
+    pub filter: Filter,
+    pub adaptive: Option<AdaptiveSampling>,
+}
+
+impl Default for Sampling {
+    // one ray through the pixel center
+    fn default() -> Sampling {
+        Sampling {
+            samples_per_pixel: 1,
+            pattern: SamplePattern::Stratified,
+            filter: Filter::Box,
+            adaptive: None,
+        }
+    }
+}
+
+impl Sampling {
+    pub fn new(samples_per_pixel: u32, pattern: SamplePattern, filter: Filter) -> Sampling {
+        Sampling {
+            samples_per_pixel,
+            pattern,
+            filter,
+            adaptive: None,
+        }
+    }
+
+    // returns number of extra samples the pixel needs, 0 when it is done
+    pub fn extra_samples(&self, stats: &SampleStats) -> u32 {
+        match self.adaptive {
+            Some(ref adaptive)
+                if stats.count < adaptive.max_samples
+                    && stats.standard_error() > adaptive.threshold =>
+            {
+                (adaptive.max_samples - stats.count).min(self.batch_size())
+            }
+            _ => 0,
+        }
+    }
+
+    // returns number of samples in a batch, adaptive sampling
+    // needs at least 2 to estimate the variance
+    fn batch_size(&self) -> u32 {
+        match self.adaptive {
+            Some(_) => self.samples_per_pixel.max(2),
+            None => self.samples_per_pixel,
+        }
+    }
+
+    // returns number of stratified grid cells along a side of the pixel
+    fn grid_cells(&self) -> u32 {
+        let batch_size = self.batch_size();
+        let cells = f64::from(batch_size).sqrt().round() as u32;
+        if batch_size >= 2 {
+            cells.max(2)
+        } else {
+            1
+        }
+    }
+
+    // returns sample positions inside the pixel square (0.0…1.0, 0.0…1.0)
+    pub fn positions(&self, rng: &mut Rng) -> Vec<(f64, f64)> {
+        if self.batch_size() <= 1 {
+            return vec![(0.5, 0.5)];
+        }
+
+        match self.pattern {
+            SamplePattern::Jittered => random_positions(self.batch_size(), rng),
+            SamplePattern::Stratified => {
+                let cells = self.grid_cells();
+                let cell_size = 1.0 / f64::from(cells);
+                let mut positions = Vec::with_capacity((cells * cells) as usize);
+                for i in 0..cells {
+                    for j in 0..cells {
+                        positions.push((
+                            (f64::from(i) + rng.next_f64()) * cell_size,
+                            (f64::from(j) + rng.next_f64()) * cell_size,
+                        ));
+                    }
+                }
+                positions
+            }
+        }
+    }
+
+    // returns positions of an extra batch of count samples, fresh ones
+    // for every batch; a part of the stratified grid would cover only
+    // the first cells of the pixel, so such batches are random instead
+    pub fn extra_positions(&self, count: u32, rng: &mut Rng) -> Vec<(f64, f64)> {
+        match self.pattern {
+            SamplePattern::Stratified if count >= self.grid_cells() * self.grid_cells() => {
+                self.positions(rng)
+            }
+            _ => random_positions(count, rng),
+        }
+    }
+}
+
+fn random_positions(count: u32, rng: &mut Rng) -> Vec<(f64, f64)> {
+    (0..count)
+        .map(|_| (rng.next_f64(), rng.next_f64()))
+        .collect()
+}
@@ -1,5 +1,8 @@
+use crate::accelerator::Accelerator;
+use crate::camera::Camera;
 use crate::point::Point;
 use crate::rendering::Intersectable;
+use crate::sampling::Sampling;
 use crate::vector3::Vector3;
 
 use image::*;
@@ -38,11 +41,15 @@ impl Color {
             blue: gamma_decode(rgba.data[2] as f32 / 255.0),
         }
     }
+    // returns relative luminance of linear color
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
     pub fn clamp(&self) -> Color {
         Color {
-            red: self.red.min(1.0).max(0.0),
-            blue: self.blue.min(1.0).max(0.0),
-            green: self.green.min(1.0).max(0.0),
+            red: self.red.clamp(0.0, 1.0),
+            blue: self.blue.clamp(0.0, 1.0),
+            green: self.green.clamp(0.0, 1.0),
         }
     }
 }
@@ -51,24 +58,22 @@ impl Mul for Color {
     type Output = Color;
 
     fn mul(self, other: Color) -> Color {
-        let c = Color {
+        Color {
             red: self.red * other.red,
             blue: self.blue * other.blue,
             green: self.green * other.green,
-        };
-        c
+        }
     }
 }
 impl Mul<f32> for Color {
     type Output = Color;
 
     fn mul(self, other: f32) -> Color {
-        let c = Color {
+        Color {
             red: self.red * other,
             blue: self.blue * other,
             green: self.green * other,
-        };
-        c
+        }
     }
 }
 impl Mul<Color> for f32 {
@@ -82,16 +87,15 @@ impl Add for Color {
     type Output = Color;
 
     fn add(self, other: Color) -> Color {
-        let c = Color {
+        Color {
             red: self.red + other.red,
             blue: self.blue + other.blue,
             green: self.green + other.green,
-        };
-        c
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub struct TextureCoords {
     pub x: f32,
     pub y: f32,
@@ -173,6 +177,202 @@ pub struct Plane {
     pub material: Material,
 }
 
+// flat disk visible from both sides, normal points to its front side
+pub struct Disk {
+    pub center: Point,
+    pub normal: Vector3,
+    pub radius: f64,
+    pub material: Material,
+}
+
+impl Disk {
+    pub fn new(center: Point, normal: Vector3, radius: f64, material: Material) -> Disk {
+        Disk {
+            center,
+            normal: normal.normalize(),
+            radius,
+            material,
+        }
+    }
+}
+
+// parallelogram spanned by two edges from the corner, a rectangle when
+// the edges are perpendicular; the front side is where edge_u turns
+// counter-clockwise to edge_v, the quad is visible from both sides
+pub struct Quad {
+    pub corner: Point,
+    pub edge_u: Vector3,
+    pub edge_v: Vector3,
+    pub material: Material,
+    // store normal of the front side
+    pub normal: Vector3,
+}
+
+impl Quad {
+    pub fn new(corner: Point, edge_u: Vector3, edge_v: Vector3, material: Material) -> Quad {
+        Quad {
+            corner,
+            edge_u,
+            edge_v,
+            material,
+            normal: edge_u.cross(&edge_v).normalize(),
+        }
+    }
+}
+
+// cylinder standing on the base point and going up along the axis
+pub struct Cylinder {
+    pub base: Point,
+    pub axis: Vector3,
+    pub radius: f64,
+    pub height: f64,
+    // closes both ends with disks
+    pub capped: bool,
+    pub material: Material,
+}
+
+impl Cylinder {
+    pub fn new(
+        base: Point,
+        axis: Vector3,
+        radius: f64,
+        height: f64,
+        capped: bool,
+        material: Material,
+    ) -> Cylinder {
+        Cylinder {
+            base,
+            axis: axis.normalize(),
+            radius,
+            height,
+            capped,
+            material,
+        }
+    }
+}
+
+// cone with the base disk center at the base point and apex
+// at the height along the axis
+pub struct Cone {
+    pub base: Point,
+    pub axis: Vector3,
+    pub radius: f64,
+    pub height: f64,
+    // closes the base with a disk
+    pub capped: bool,
+    pub material: Material,
+}
+
+impl Cone {
+    pub fn new(
+        base: Point,
+        axis: Vector3,
+        radius: f64,
+        height: f64,
+        capped: bool,
+        material: Material,
+    ) -> Cone {
+        Cone {
+            base,
+            axis: axis.normalize(),
+            radius,
+            height,
+            capped,
+            material,
+        }
+    }
+
+    pub fn apex(&self) -> Point {
+        self.base + self.axis * self.height
+    }
+}
+
+// torus around the center with the hole along the axis, major radius
+// goes from the center to the middle of the tube, minor is the tube radius
+pub struct Torus {
+    pub center: Point,
+    pub axis: Vector3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+}
+
+impl Torus {
+    pub fn new(
+        center: Point,
+        axis: Vector3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: Material,
+    ) -> Torus {
+        Torus {
+            center,
+            axis: axis.normalize(),
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+}
+
+pub struct AxisAlignedBox {
+    pub min: Point,
+    pub max: Point,
+    pub material: Material,
+}
+
+impl AxisAlignedBox {
+    pub fn new(min: Point, max: Point, material: Material) -> AxisAlignedBox {
+        AxisAlignedBox { min, max, material }
+    }
+}
+
+// triangle with vertices in counter-clockwise order
+// when looking at its front side
+pub struct Triangle {
+    pub vertices: [Point; 3],
+    // per-vertex normals for smooth shading, flat shading if None
+    pub normals: Option<[Vector3; 3]>,
+    pub texture_coords: [TextureCoords; 3],
+    pub material: Material,
+    // store geometric normal
+    pub normal: Vector3,
+}
+
+impl Triangle {
+    pub fn new(v0: Point, v1: Point, v2: Point, material: Material) -> Triangle {
+        Triangle {
+            vertices: [v0, v1, v2],
+            normals: None,
+            texture_coords: [
+                TextureCoords { x: 0.0, y: 0.0 },
+                TextureCoords { x: 1.0, y: 0.0 },
+                TextureCoords { x: 0.0, y: 1.0 },
+            ],
+            material,
+            normal: (v1 - v0).cross(&(v2 - v0)).normalize(),
+        }
+    }
+
+    // returns barycentric weights of the vertices for the point in the triangle plane
+    pub fn barycentric(&self, point: &Point) -> (f64, f64, f64) {
+        let [v0, v1, v2] = self.vertices;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+        let to_point = *point - v0;
+        let d00 = edge1.dot(&edge1);
+        let d01 = edge1.dot(&edge2);
+        let d11 = edge2.dot(&edge2);
+        let d20 = to_point.dot(&edge1);
+        let d21 = to_point.dot(&edge2);
+        let denom = d00 * d11 - d01 * d01;
+        let v = (d11 * d20 - d01 * d21) / denom;
+        let w = (d00 * d21 - d01 * d20) / denom;
+
+        (1.0 - v - w, v, w)
+    }
+}
+
 pub struct DirectLight {
     pub direction: Vector3,
     pub color: Color,
@@ -193,7 +393,7 @@ pub enum Light {
 impl Light {
     pub fn distance(&self, hit_point: &Point) -> f64 {
         match self {
-            Light::Direct(_) => ::std::f64::INFINITY,
+            Light::Direct(_) => f64::INFINITY,
             Light::Spherical(l) => (l.position - *hit_point).length(),
         }
     }
@@ -226,8 +426,12 @@ impl Light {
 pub struct Scene {
     pub width: u32,
     pub height: u32,
-    pub fov: f64,
+    pub camera: Camera,
+    pub sampling: Sampling,
     pub objects: Vec<Box<dyn Intersectable + Sync + Send>>,
+    // acceleration structure over objects, see Scene::build_bvh
+    // and Scene::build_kd_tree, objects are tested one by one if None
+    pub accelerator: Option<Box<dyn Accelerator + Sync + Send>>,
     pub lights: Vec<Light>,
     pub bg_color: Color,
 }
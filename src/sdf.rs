@@ -0,0 +1,130 @@
+use crate::aabb::Aabb;
+use crate::point::Point;
+use crate::rendering::{spherical_texture_coords, Intersectable, Intersection, Ray, Surface};
+use crate::scene::{Material, TextureCoords};
+use crate::vector3::Vector3;
+
+// limits of sphere tracing
+const MAX_STEPS: u32 = 256;
+const HIT_DISTANCE: f64 = 1e-6;
+// step of finite differences for normals
+const NORMAL_DELTA: f64 = 1e-5;
+
+// signed distance to the surface, negative inside; it must never
+// overestimate the distance or the surface may be stepped over
+pub type DistanceFn = Box<dyn Fn(&Point) -> f64 + Sync + Send>;
+
+// SdfObject is a surface given by a signed distance function, functions
+// below build a tree of shapes and blends; rays are marched only inside
+// the bounds which must contain the surface
+pub struct SdfObject {
+    pub distance: DistanceFn,
+    pub bounds: Aabb,
+    pub material: Material,
+}
+
+impl SdfObject {
+    pub fn new(distance: DistanceFn, bounds: Aabb, material: Material) -> SdfObject {
+        SdfObject {
+            distance,
+            bounds,
+            material,
+        }
+    }
+}
+
+pub fn sphere(center: Point, radius: f64) -> DistanceFn {
+    Box::new(move |p| (*p - center).length() - radius)
+}
+
+// box around the center with edges rounded by the radius
+pub fn rounded_box(center: Point, half_size: Vector3, radius: f64) -> DistanceFn {
+    Box::new(move |p| {
+        let d = *p - center;
+        let q = Vector3::new(
+            d.x.abs() - half_size.x + radius,
+            d.y.abs() - half_size.y + radius,
+            d.z.abs() - half_size.z + radius,
+        );
+        let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+        let inside = q.x.max(q.y).max(q.z).min(0.0);
+        outside + inside - radius
+    })
+}
+
+pub fn union(a: DistanceFn, b: DistanceFn) -> DistanceFn {
+    Box::new(move |p| a(p).min(b(p)))
+}
+
+pub fn intersection(a: DistanceFn, b: DistanceFn) -> DistanceFn {
+    Box::new(move |p| a(p).max(b(p)))
+}
+
+// a without b
+pub fn difference(a: DistanceFn, b: DistanceFn) -> DistanceFn {
+    Box::new(move |p| a(p).max(-b(p)))
+}
+
+// union blending the shapes together within the distance k
+pub fn smooth_union(a: DistanceFn, b: DistanceFn, k: f64) -> DistanceFn {
+    Box::new(move |p| {
+        let (da, db) = (a(p), b(p));
+        let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+        db + (da - db) * h - k * h * (1.0 - h)
+    })
+}
+
+// returns gradient of the field by central differences
+pub(crate) fn gradient(field: &dyn Fn(&Point) -> f64, point: &Point) -> Vector3 {
+    let dx = Vector3::new(NORMAL_DELTA, 0.0, 0.0);
+    let dy = Vector3::new(0.0, NORMAL_DELTA, 0.0);
+    let dz = Vector3::new(0.0, 0.0, NORMAL_DELTA);
+    Vector3::new(
+        field(&(*point + dx)) - field(&(*point - dx)),
+        field(&(*point + dy)) - field(&(*point - dy)),
+        field(&(*point + dz)) - field(&(*point - dz)),
+    ) * (0.5 / NORMAL_DELTA)
+}
+
+impl Intersectable for SdfObject {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        // sphere tracing, the distance to the surface is a safe step
+        let (enter, exit) = self.bounds.intersect(ray, f64::INFINITY)?;
+        let mut t = enter;
+        for _ in 0..MAX_STEPS {
+            if t > exit {
+                return None;
+            }
+            // the ray may start inside, step by the absolute distance then
+            let d = (self.distance)(&(ray.origin + ray.direction * t)).abs();
+            if d < HIT_DISTANCE * t.max(1.0) {
+                return Some(t);
+            }
+            t += d;
+        }
+        None
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|distance| Intersection::on_surface(ray, distance, self))
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+impl Surface for SdfObject {
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        gradient(&self.distance, hit_point).normalize()
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn texture_coords(&self, point: &Point) -> TextureCoords {
+        spherical_texture_coords(&self.surface_normal(point))
+    }
+}
@@ -0,0 +1,127 @@
+use crate::aabb::Aabb;
+use crate::matrix4::Matrix4;
+use crate::point::Point;
+use crate::rendering::{Intersectable, Intersection, Ray, Span};
+
+// Transformed places an object defined in its own space into the scene,
+// e.g. ellipsoids from a scaled Sphere or rotated boxes; wrap the object
+// in Arc to reuse one mesh at many positions without copying it
+pub struct Transformed<T: Intersectable> {
+    pub object: T,
+    // object to world space
+    pub matrix: Matrix4,
+    // world to object space
+    pub inverse: Matrix4,
+}
+
+impl<T: Intersectable> Transformed<T> {
+    // panics if the matrix is not invertible
+    pub fn new(object: T, matrix: Matrix4) -> Transformed<T> {
+        Transformed {
+            object,
+            matrix,
+            inverse: matrix
+                .inverse()
+                .expect("transform matrix is not invertible"),
+        }
+    }
+}
+
+// returns ray in object space and length of its unnormalized direction,
+// world distance is the object space distance divided by the length
+pub(crate) fn object_ray(inverse: &Matrix4, ray: &Ray) -> (Ray, f64) {
+    let direction = inverse.transform_vector(&ray.direction);
+    let scale = direction.length();
+    (
+        Ray {
+            origin: inverse.transform_point(&ray.origin),
+            direction: direction * scale.recip(),
+        },
+        scale,
+    )
+}
+
+// returns intersection in world space, normals transform by the inverse transpose
+pub(crate) fn world_intersection<'a>(
+    inverse: &Matrix4,
+    intersection: Intersection<'a>,
+    scale: f64,
+) -> Intersection<'a> {
+    let normal = inverse.transpose().transform_vector(&intersection.normal);
+    Intersection {
+        distance: intersection.distance / scale,
+        normal: normal.normalize(),
+        ..intersection
+    }
+}
+
+// returns world bounds of the object bounds, unbounded stays unbounded
+pub(crate) fn world_bounds(matrix: &Matrix4, bounds: &Aabb) -> Aabb {
+    if bounds.is_empty() || !bounds.is_finite() {
+        return *bounds;
+    }
+    let corners: Vec<Point> = (0..8)
+        .map(|i| {
+            let x = if i & 1 == 0 {
+                bounds.min.x
+            } else {
+                bounds.max.x
+            };
+            let y = if i & 2 == 0 {
+                bounds.min.y
+            } else {
+                bounds.max.y
+            };
+            let z = if i & 4 == 0 {
+                bounds.min.z
+            } else {
+                bounds.max.z
+            };
+            matrix.transform_point(&Point::new(x, y, z))
+        })
+        .collect();
+    Aabb::from_points(&corners)
+}
+
+impl<T: Intersectable> Intersectable for Transformed<T> {
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (object_ray, scale) = object_ray(&self.inverse, ray);
+        self.object
+            .intersect(&object_ray)
+            .map(|distance| distance / scale)
+    }
+
+    fn intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let (object_ray, scale) = object_ray(&self.inverse, ray);
+        self.object
+            .intersection(&object_ray)
+            .map(|intersection| world_intersection(&self.inverse, intersection, scale))
+    }
+
+    fn bounds(&self) -> Aabb {
+        world_bounds(&self.matrix, &self.object.bounds())
+    }
+
+    fn occludes(&self, ray: &Ray, max_distance: f64) -> bool {
+        let (object_ray, scale) = object_ray(&self.inverse, ray);
+        self.object.occludes(&object_ray, max_distance * scale)
+    }
+
+    fn is_solid(&self) -> bool {
+        self.object.is_solid()
+    }
+
+    fn spans(&self, ray: &Ray) -> Option<Vec<Span<'_>>> {
+        let (object_ray, scale) = object_ray(&self.inverse, ray);
+        let spans = self.object.spans(&object_ray)?;
+        Some(
+            spans
+                .into_iter()
+                .map(|span| Span {
+                    enter: world_intersection(&self.inverse, span.enter, scale),
+                    exit: world_intersection(&self.inverse, span.exit, scale),
+                })
+                .collect(),
+        )
+    }
+}
@@ -17,7 +17,7 @@ impl Vector3 {
     }
 
     pub fn norm(&self) -> f64 {
-        (self.x * self.x + self.y * self.y + self.z * self.z)
+        self.x * self.x + self.y * self.y + self.z * self.z
     }
 
     pub fn normalize(&self) -> Vector3 {
@@ -41,6 +41,19 @@ impl Vector3 {
             z: self.x * other.y - self.y * other.x,
         }
     }
+
+    // returns two unit vectors perpendicular to this unit vector and to each other
+    pub fn orthonormal_basis(&self) -> (Vector3, Vector3) {
+        let helper = if self.x.abs() > 0.9 {
+            Vector3::new(0.0, 1.0, 0.0)
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let u = self.cross(&helper).normalize();
+        let v = self.cross(&u);
+
+        (u, v)
+    }
 }
 
 impl Add for Vector3 {